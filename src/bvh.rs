@@ -0,0 +1,224 @@
+use crate::type_structs::{BoundingBox, Ray, Vec3D, EPSILON, INFINITY};
+
+/// Number of buckets centroids are binned into when evaluating split candidates
+static SAH_BUCKETS: usize = 12;
+/// Leaves are not split further once they hold this many primitives or fewer
+static MAX_LEAF_SIZE: usize = 4;
+/// Relative cost of one ray-box test compared to one primitive intersection
+static TRAVERSAL_COST: f64 = 0.125;
+
+/// # A node of the hierarchy
+/// Leaves reference a run of `count` primitives starting at `first`, interior nodes store the index of their second
+/// child, the first child always directly follows its parent
+struct BvhNode {
+    bounds: BoundingBox,
+    first: usize,
+    count: usize,
+    second_child: usize,
+}
+
+/// # Bounding volume hierarchy built with the surface area heuristic
+/// Primitives are referred to by the index they were given at build time, which lets the same structure
+/// accelerate the objects of a RenderScene and the triangles of a mesh
+pub(crate) struct Bvh {
+    nodes: Vec<BvhNode>,
+    primitive_indices: Vec<usize>,
+}
+
+/// Primitive reference used while building
+struct BuildPrimitive {
+    index: usize,
+    bounds: BoundingBox,
+    centroid: [f64; 3],
+}
+
+/// Component of a vector along axis 0 (x), 1 (y) or 2 (z)
+fn component(vector: &Vec3D, axis: usize) -> f64 {
+    match axis {
+        0 => vector.x,
+        1 => vector.y,
+        _ => vector.z,
+    }
+}
+
+impl Bvh {
+    /// Build the hierarchy over (index, bounding box) pairs
+    pub(crate) fn build(primitives: Vec<(usize, BoundingBox)>) -> Bvh {
+        let mut build_primitives: Vec<BuildPrimitive> = primitives.into_iter().map(|(index, bounds)| {
+            let centroid = bounds.centroid();
+            BuildPrimitive { index, bounds, centroid: [centroid.x, centroid.y, centroid.z] }
+        }).collect();
+        let mut bvh = Bvh { nodes: Vec::new(), primitive_indices: Vec::new() };
+        if !build_primitives.is_empty() {
+            let count = build_primitives.len();
+            bvh.build_recursive(&mut build_primitives, 0, count);
+        }
+        bvh.primitive_indices = build_primitives.iter().map(|primitive| primitive.index).collect();
+        bvh
+    }
+
+    /// Build the subtree over primitives[start..end], returns the index of its root node
+    fn build_recursive(&mut self, primitives: &mut [BuildPrimitive], start: usize, end: usize) -> usize {
+        let node_index = self.nodes.len();
+        let mut bounds = primitives[start].bounds;
+        let mut centroid_bounds = BoundingBox::from_point(primitives[start].bounds.centroid());
+        for primitive in &primitives[start + 1..end] {
+            bounds = bounds.union(&primitive.bounds);
+            centroid_bounds = centroid_bounds.union(&BoundingBox::from_point(primitive.bounds.centroid()));
+        }
+        self.nodes.push(BvhNode { bounds, first: start, count: end - start, second_child: 0 });
+
+        let count = end - start;
+        if count <= MAX_LEAF_SIZE {
+            return node_index;
+        }
+
+        // split along the axis where the centroids are spread the most
+        let extent = centroid_bounds.max.subtract(&centroid_bounds.min);
+        let split_axis = if extent.x >= extent.y && extent.x >= extent.z { 0 } else if extent.y >= extent.z { 1 } else { 2 };
+        let axis_min = component(&centroid_bounds.min, split_axis);
+        let axis_extent = component(&extent, split_axis);
+        if axis_extent <= 0.0 {
+            // all centroids coincide, no split can separate them
+            return node_index;
+        }
+
+        // bin the centroids and evaluate the surface area heuristic at every bucket boundary
+        let bucket_of = |primitive: &BuildPrimitive| {
+            let relative = (primitive.centroid[split_axis] - axis_min) / axis_extent;
+            usize::min((relative * SAH_BUCKETS as f64) as usize, SAH_BUCKETS - 1)
+        };
+        let mut bucket_counts = vec![0usize; SAH_BUCKETS];
+        let mut bucket_bounds: Vec<Option<BoundingBox>> = vec![None; SAH_BUCKETS];
+        for primitive in &primitives[start..end] {
+            let bucket = bucket_of(primitive);
+            bucket_counts[bucket] += 1;
+            bucket_bounds[bucket] = Some(match bucket_bounds[bucket] {
+                Some(existing) => existing.union(&primitive.bounds),
+                None => primitive.bounds,
+            });
+        }
+
+        let mut best_cost = INFINITY;
+        let mut best_split = 0;
+        for split in 1..SAH_BUCKETS {
+            let (left_bounds, left_count) = merge_buckets(&bucket_bounds[..split], &bucket_counts[..split]);
+            let (right_bounds, right_count) = merge_buckets(&bucket_bounds[split..], &bucket_counts[split..]);
+            if left_count == 0 || right_count == 0 {
+                continue;
+            }
+            let cost = TRAVERSAL_COST + (left_bounds.map_or(0.0, |b| b.surface_area()) * left_count as f64
+                + right_bounds.map_or(0.0, |b| b.surface_area()) * right_count as f64) / bounds.surface_area().max(EPSILON);
+            if cost < best_cost {
+                best_cost = cost;
+                best_split = split;
+            }
+        }
+
+        // keep a leaf if splitting does not pay off
+        if best_split == 0 || best_cost >= count as f64 {
+            return node_index;
+        }
+
+        // partition primitives by bucket
+        let mut middle = start;
+        for i in start..end {
+            if bucket_of(&primitives[i]) < best_split {
+                primitives.swap(i, middle);
+                middle += 1;
+            }
+        }
+
+        self.build_recursive(primitives, start, middle);
+        let second_child = self.build_recursive(primitives, middle, end);
+        let node = &mut self.nodes[node_index];
+        node.count = 0;
+        node.second_child = second_child;
+        node_index
+    }
+
+    /// Find the closest primitive hit by the ray.
+    /// `intersect` is called with a primitive index and returns the hit distance, or 0.0 for a miss (as SceneObject::intersect does)
+    pub(crate) fn closest_hit<F: Fn(usize) -> f64>(&self, ray: &Ray, intersect: F) -> Option<(usize, f64)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inverse_direction = [1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z];
+        let mut closest: Option<(usize, f64)> = None;
+        let mut closest_distance = INFINITY;
+        let mut stack = vec![0usize];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if !node.bounds.hit(ray, &inverse_direction, closest_distance) {
+                continue;
+            }
+            if node.count > 0 {
+                for &primitive in &self.primitive_indices[node.first..node.first + node.count] {
+                    let distance = intersect(primitive);
+                    if distance > EPSILON && distance < closest_distance {
+                        closest_distance = distance;
+                        closest = Some((primitive, distance));
+                    }
+                }
+            } else {
+                stack.push(node.second_child);
+                stack.push(node_index + 1);
+            }
+        }
+        closest
+    }
+}
+
+/// Union of the non-empty buckets and the total number of primitives in them
+fn merge_buckets(bounds: &[Option<BoundingBox>], counts: &[usize]) -> (Option<BoundingBox>, usize) {
+    let mut merged: Option<BoundingBox> = None;
+    for bucket in bounds.iter().flatten() {
+        merged = Some(match merged {
+            Some(existing) => existing.union(bucket),
+            None => *bucket,
+        });
+    }
+    (merged, counts.iter().sum())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+    use crate::type_structs::{DiffuseColour, RenderScene, SceneObject, Sphere};
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    #[test]
+    fn bvh_matches_brute_force_test() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let mut objects_list: Vec<Box<dyn SceneObject>> = Vec::new();
+        for _ in 0..200 {
+            objects_list.push(Box::new(Sphere {
+                center: Vec3D { x: rng.gen_range(-10.0, 10.0), y: rng.gen_range(-10.0, 10.0), z: rng.gen_range(-10.0, 10.0) },
+                radius: rng.gen_range(0.1, 1.0),
+                material: Material::Diffuse { colour: DiffuseColour { r: 12.0, g: 12.0, b: 12.0 } },
+            }));
+        }
+        let mut scene = RenderScene::new(objects_list).unwrap();
+        scene.build_bvh();
+
+        for _ in 0..1000 {
+            let mut direction = Vec3D { x: rng.gen_range(-1.0, 1.0), y: rng.gen_range(-1.0, 1.0), z: rng.gen_range(-1.0, 1.0) };
+            direction.norm();
+            let ray = Ray {
+                origin: Vec3D { x: rng.gen_range(-12.0, 12.0), y: rng.gen_range(-12.0, 12.0), z: rng.gen_range(-12.0, 12.0) },
+                direction,
+            };
+            let accelerated = scene.intersect(&ray).map(|hit| hit.distance);
+            let brute_force = scene.intersect_brute_force(&ray).map(|hit| hit.distance);
+            assert_eq!(accelerated, brute_force);
+        }
+    }
+
+    #[test]
+    fn bvh_empty_test() {
+        let bvh = Bvh::build(Vec::new());
+        let ray = Ray { origin: Vec3D { x: 0.0, y: 0.0, z: 0.0 }, direction: Vec3D { x: 0.0, y: 0.0, z: -1.0 } };
+        assert!(bvh.closest_hit(&ray, |_| 1.0).is_none());
+    }
+}
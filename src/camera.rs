@@ -0,0 +1,193 @@
+use std::f64::consts::PI;
+
+use crate::type_structs::{Ray, Vec3D};
+
+/// # A look-at thin lens camera
+/// Placed at `eye` and looking towards `target`, with `up` giving the rough upwards direction of the image.
+/// `vertical_fov` is the full vertical opening angle in radians. When `aspect_ratio` (width / height) is None the
+/// aspect ratio of the rendered image is used.
+/// Rays start on a lens disk of `aperture_radius` around the eye and converge on the plane `focus_distance` in front of
+/// it (the distance to `target` when None). An aperture of 0 gives a pinhole camera with everything in focus
+#[derive(Clone, Copy)]
+pub struct Camera {
+    pub eye: Vec3D,
+    pub target: Vec3D,
+    pub up: Vec3D,
+    pub vertical_fov: f64,
+    pub aspect_ratio: Option<f64>,
+    pub aperture_radius: f64,
+    pub focus_distance: Option<f64>,
+}
+
+/// Orthonormal basis and image plane extent derived from a Camera, computed once per render
+pub(crate) struct CameraFrame {
+    origin: Vec3D,
+    right: Vec3D,
+    up: Vec3D,
+    forward: Vec3D,
+    half_width: f64,
+    half_height: f64,
+    aperture_radius: f64,
+    focus_distance: f64,
+}
+
+impl Default for Camera {
+    /// The camera of the original demo: at the origin looking down -z with a 90 degree field of view
+    fn default() -> Self {
+        Camera {
+            eye: Vec3D {x: 0.0, y: 0.0, z: 0.0},
+            target: Vec3D {x: 0.0, y: 0.0, z: -1.0},
+            up: Vec3D {x: 0.0, y: 1.0, z: 0.0},
+            vertical_fov: PI/2.0,
+            aspect_ratio: None,
+            aperture_radius: 0.0,
+            focus_distance: None,
+        }
+    }
+}
+
+impl Camera {
+    /// Build the camera basis for an image of the given size.
+    /// Returns None if eye and target coincide or up is parallel to the viewing direction
+    pub(crate) fn frame(&self, width: i32, height: i32) -> Option<CameraFrame> {
+        let mut forward = self.target.subtract(&self.eye);
+        let target_distance = forward.length();
+        if target_distance == 0.0 {
+            return None;
+        }
+        forward.norm();
+        let mut right = forward.cross(&self.up);
+        if right.length() < 1e-12 {
+            return None;
+        }
+        right.norm();
+        let up = right.cross(&forward);
+        let aspect_ratio = self.aspect_ratio.unwrap_or(width as f64 / height as f64);
+        let half_height = (self.vertical_fov/2.0).tan();
+        Some(CameraFrame {
+            origin: self.eye,
+            right,
+            up,
+            forward,
+            half_width: aspect_ratio*half_height,
+            half_height,
+            aperture_radius: self.aperture_radius,
+            focus_distance: self.focus_distance.unwrap_or(target_distance),
+        })
+    }
+}
+
+/// Map a point of the unit square onto the unit disk with Shirley's concentric mapping, which keeps strata intact
+pub(crate) fn concentric_disk(u: f64, v: f64) -> (f64, f64) {
+    let a = 2.0*u - 1.0;
+    let b = 2.0*v - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (radius, angle) = if a.abs() > b.abs() {
+        (a, PI/4.0*(b/a))
+    }else {
+        (b, PI/2.0 - PI/4.0*(a/b))
+    };
+    (radius*angle.cos(), radius*angle.sin())
+}
+
+impl CameraFrame {
+    /// Primary ray through a point of the image plane given in normalized device coordinates:
+    /// x runs from -1 (left edge) to 1 (right edge), y from -1 (bottom edge) to 1 (top edge).
+    /// `lens_sample` is a point of the unit square choosing where on the lens the ray starts
+    pub(crate) fn generate_ray(&self, x: f64, y: f64, lens_sample: (f64, f64)) -> Ray {
+        let mut direction = self.forward
+            .add(&self.right.scalar_mult(x*self.half_width))
+            .add(&self.up.scalar_mult(y*self.half_height));
+        if self.aperture_radius <= 0.0 {
+            direction.norm();
+            return Ray {
+                origin: self.origin,
+                direction,
+            };
+        }
+        // every ray through this image point meets the pinhole ray on the plane of focus
+        let focus_point = self.origin.add(&direction.scalar_mult(self.focus_distance));
+        let (disk_x, disk_y) = concentric_disk(lens_sample.0, lens_sample.1);
+        let lens_point = self.origin
+            .add(&self.right.scalar_mult(disk_x*self.aperture_radius))
+            .add(&self.up.scalar_mult(disk_y*self.aperture_radius));
+        let mut direction = focus_point.subtract(&lens_point);
+        direction.norm();
+        Ray {
+            origin: lens_point,
+            direction,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{a} != {b}");
+    }
+
+    #[test]
+    fn default_camera_test() {
+        // matches the original hard-coded camera plane at z = -1 spanning [-1, 1] on both axes
+        let frame = Camera::default().frame(800, 800).unwrap();
+        let center = frame.generate_ray(0.0, 0.0, (0.5, 0.5));
+        assert_close(center.direction.z, -1.0);
+        let corner = frame.generate_ray(1.0, 1.0, (0.5, 0.5));
+        let mut expected = Vec3D {x: 1.0, y: 1.0, z: -1.0};
+        expected.norm();
+        assert_close(corner.direction.x, expected.x);
+        assert_close(corner.direction.y, expected.y);
+        assert_close(corner.direction.z, expected.z);
+    }
+
+    #[test]
+    fn look_at_camera_test() {
+        let camera = Camera {
+            eye: Vec3D {x: 5.0, y: 0.0, z: 0.0},
+            target: Vec3D {x: 0.0, y: 0.0, z: 0.0},
+            up: Vec3D {x: 0.0, y: 1.0, z: 0.0},
+            vertical_fov: PI/3.0,
+            aspect_ratio: None,
+            aperture_radius: 0.0,
+            focus_distance: None,
+        };
+        let frame = camera.frame(200, 100).unwrap();
+        let center = frame.generate_ray(0.0, 0.0, (0.5, 0.5));
+        assert_close(center.origin.x, 5.0);
+        assert_close(center.direction.x, -1.0);
+        // right edge of a 2:1 image looking down -x is towards -z, the top edge is +y at half the vertical fov
+        let right = frame.generate_ray(1.0, 0.0, (0.5, 0.5));
+        assert!(right.direction.z < 0.0);
+        let top = frame.generate_ray(0.0, 1.0, (0.5, 0.5));
+        assert_close(top.direction.y.atan2(-top.direction.x), PI/6.0);
+
+        let degenerate = Camera { up: Vec3D {x: 1.0, y: 0.0, z: 0.0}, ..camera };
+        assert!(degenerate.frame(200, 100).is_none());
+    }
+
+    #[test]
+    fn thin_lens_focus_test() {
+        let camera = Camera {
+            aperture_radius: 0.2,
+            focus_distance: Some(4.0),
+            ..Camera::default()
+        };
+        let frame = camera.frame(100, 100).unwrap();
+        let pinhole = Camera::default().frame(100, 100).unwrap().generate_ray(0.3, -0.2, (0.5, 0.5));
+        let focus_point = pinhole.origin.add(&pinhole.direction.scalar_mult(4.0/-pinhole.direction.z));
+        for lens_sample in [(0.0, 0.0), (0.9, 0.1), (0.25, 0.75), (1.0, 1.0)] {
+            let ray = frame.generate_ray(0.3, -0.2, lens_sample);
+            // rays start on the lens disk and all pass through the same point on the plane of focus
+            assert!(ray.origin.length() <= 0.2 + 1e-9);
+            assert_close(ray.origin.z, 0.0);
+            let travel = (focus_point.z - ray.origin.z)/ray.direction.z;
+            let hit = ray.origin.add(&ray.direction.scalar_mult(travel));
+            assert_close(hit.x, focus_point.x);
+            assert_close(hit.y, focus_point.y);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::fmt;
+use std::fs;
+
+use crate::film::Film;
+use crate::rendering_equation::{Accumulation, FilteredPixel, PixelSamples, RenderSettings};
+use crate::type_structs::DiffuseColour;
+
+// Checkpoints of progressive renders
+//
+// A checkpoint holds everything needed to carry on accumulating samples later: the per-pixel filtered radiance sums
+// and filter weights, sample counts and luminance statistics for adaptive sampling, the number of passes taken and
+// the seed the per-sample random number generators are derived from.
+// It also stores a fingerprint of the scene and of the settings that change the converged image, a checkpoint is
+// refused if either differs.
+//
+// Layout, all numbers little-endian:
+// MAGIC, fingerprint u64, seed u64, passes u32, width u32, height u32,
+// then for every pixel, row by row from the top, the filter weighted r, g and b sums and the sum of filter weights as f64,
+// the sample count as u32 and the mean luminance and sum of squared luminance deviations as f64
+
+static MAGIC: &[u8; 8] = b"RRCKPT01";
+
+/// # Errors produced while saving or loading a checkpoint
+#[derive(Debug)]
+pub enum CheckpointError {
+    /// The file could not be read or written
+    Io { path: String, source: std::io::Error },
+    /// The file is not a checkpoint or is truncated
+    Malformed { path: String },
+    /// The checkpoint belongs to a different scene, resolution, path depth or reconstruction filter
+    SceneMismatch { path: String },
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::Io { path, source } => write!(f, "{path}: {source}"),
+            CheckpointError::Malformed { path } => write!(f, "{path}: not a valid checkpoint file"),
+            CheckpointError::SceneMismatch { path } => write!(f, "{path}: the checkpoint was made for a different scene or render settings"),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CheckpointError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// 64-bit FNV-1a, used because it is stable across Rust versions unlike the standard library's hasher
+pub(crate) fn fnv1a(bytes: &[u8], mut hash: u64) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01B3);
+    }
+    hash
+}
+
+/// Starting value of an FNV-1a hash
+pub(crate) static FNV_OFFSET: u64 = 0xCBF2_9CE4_8422_2325;
+
+/// Fingerprint of a scene together with the settings that change what the render converges to
+pub fn render_fingerprint(scene_fingerprint: u64, settings: &RenderSettings) -> u64 {
+    let mut hash = fnv1a(&scene_fingerprint.to_le_bytes(), FNV_OFFSET);
+    for value in [settings.width, settings.height, settings.max_depth, settings.filter as i32] {
+        hash = fnv1a(&value.to_le_bytes(), hash);
+    }
+    fnv1a(&settings.reconstruction_radius().to_le_bytes(), hash)
+}
+
+/// Reads little-endian numbers from the checkpoint bytes, None once the data runs out
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (head, rest) = self.data.split_first_chunk::<N>()?;
+        self.data = rest;
+        Some(*head)
+    }
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes::<4>().map(u32::from_le_bytes)
+    }
+    fn u64(&mut self) -> Option<u64> {
+        self.bytes::<8>().map(u64::from_le_bytes)
+    }
+    fn f64(&mut self) -> Option<f64> {
+        self.bytes::<8>().map(f64::from_le_bytes)
+    }
+}
+
+/// Serialize an accumulation, see the layout above
+fn encode(accumulation: &Accumulation, fingerprint: u64, settings: &RenderSettings) -> Vec<u8> {
+    let mut data = Vec::with_capacity(36 + accumulation.pixels.pixels().len()*52);
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&fingerprint.to_le_bytes());
+    data.extend_from_slice(&accumulation.seed.to_le_bytes());
+    data.extend_from_slice(&(accumulation.passes as u32).to_le_bytes());
+    data.extend_from_slice(&(settings.width as u32).to_le_bytes());
+    data.extend_from_slice(&(settings.height as u32).to_le_bytes());
+    for (filtered, pixel) in accumulation.filtered.pixels().iter().zip(accumulation.pixels.pixels()) {
+        let sum = filtered.weighted_sum;
+        for value in [sum.r, sum.g, sum.b, filtered.weight] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&pixel.count.to_le_bytes());
+        data.extend_from_slice(&pixel.mean.to_le_bytes());
+        data.extend_from_slice(&pixel.m2.to_le_bytes());
+    }
+    data
+}
+
+/// Deserialize an accumulation, checking it was made for the same fingerprint and image size
+fn decode(data: &[u8], fingerprint: u64, settings: &RenderSettings, path: &str) -> Result<Accumulation, CheckpointError> {
+    let malformed = || CheckpointError::Malformed { path: path.to_string() };
+    let mut reader = Reader { data };
+    if reader.bytes::<8>().as_ref() != Some(MAGIC) {
+        return Err(malformed());
+    }
+    let stored_fingerprint = reader.u64().ok_or_else(malformed)?;
+    let seed = reader.u64().ok_or_else(malformed)?;
+    let passes = reader.u32().ok_or_else(malformed)?;
+    let width = reader.u32().ok_or_else(malformed)?;
+    let height = reader.u32().ok_or_else(malformed)?;
+    if stored_fingerprint != fingerprint || width != settings.width as u32 || height != settings.height as u32 {
+        return Err(CheckpointError::SceneMismatch { path: path.to_string() });
+    }
+
+    let pixel_count = width as usize*height as usize;
+    let mut filtered = Vec::with_capacity(pixel_count);
+    let mut pixels = Vec::with_capacity(pixel_count);
+    for _ in 0..pixel_count {
+        let r = reader.f64().ok_or_else(malformed)?;
+        let g = reader.f64().ok_or_else(malformed)?;
+        let b = reader.f64().ok_or_else(malformed)?;
+        filtered.push(FilteredPixel {
+            weighted_sum: DiffuseColour {r, g, b},
+            weight: reader.f64().ok_or_else(malformed)?,
+        });
+        pixels.push(PixelSamples {
+            count: reader.u32().ok_or_else(malformed)?,
+            mean: reader.f64().ok_or_else(malformed)?,
+            m2: reader.f64().ok_or_else(malformed)?,
+        });
+    }
+    if !reader.data.is_empty() {
+        return Err(malformed());
+    }
+    Ok(Accumulation {
+        pixels: Film::from_pixels(width as i32, height as i32, pixels).ok_or_else(malformed)?,
+        filtered: Film::from_pixels(width as i32, height as i32, filtered).ok_or_else(malformed)?,
+        passes: passes as i32,
+        seed,
+    })
+}
+
+/// Write the accumulation of a progressive render to `path`
+pub fn save_checkpoint(accumulation: &Accumulation, fingerprint: u64, settings: &RenderSettings, path: &str) -> Result<(), CheckpointError> {
+    // write next to the old checkpoint first, so an interrupted save does not destroy it
+    let temporary = format!("{path}.tmp");
+    let io_error = |source| CheckpointError::Io { path: path.to_string(), source };
+    fs::write(&temporary, encode(accumulation, fingerprint, settings)).map_err(io_error)?;
+    fs::rename(&temporary, path).map_err(io_error)
+}
+
+/// Read a checkpoint from `path`, refusing it if it was made for a different scene or render settings
+pub fn load_checkpoint(fingerprint: u64, settings: &RenderSettings, path: &str) -> Result<Accumulation, CheckpointError> {
+    let data = fs::read(path).map_err(|source| CheckpointError::Io { path: path.to_string(), source })?;
+    decode(&data, fingerprint, settings, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Filter;
+
+    #[test]
+    fn checkpoint_round_trip_test() {
+        let settings = RenderSettings { width: 3, height: 2, ..RenderSettings::default() };
+        let mut accumulation = Accumulation::new(&settings, 42);
+        accumulation.passes = 7;
+        for row in 0..2 {
+            for column in 0..3 {
+                let filtered = accumulation.filtered.get_mut(column, row);
+                filtered.weighted_sum = DiffuseColour {r: (row*3 + column) as f64, g: 0.25, b: 1e9};
+                filtered.weight = -0.5;
+                let pixel = accumulation.pixels.get_mut(column, row);
+                pixel.count = 7;
+                pixel.m2 = 0.5;
+            }
+        }
+        let fingerprint = render_fingerprint(1234, &settings);
+        let data = encode(&accumulation, fingerprint, &settings);
+
+        let loaded = decode(&data, fingerprint, &settings, "test.ckpt").unwrap();
+        assert_eq!(loaded.passes, 7);
+        assert_eq!(loaded.seed, 42);
+        assert_eq!(loaded.sample_counts().pixels(), &[7; 6]);
+        assert_eq!(loaded.filtered.get(2, 1).weighted_sum.r, 5.0);
+        assert_eq!(loaded.filtered.get(2, 1).weighted_sum.b, 1e9);
+        assert_eq!(loaded.filtered.get(2, 1).weight, -0.5);
+        assert_eq!(loaded.pixels.get(2, 1).m2, 0.5);
+
+        // a different scene, resolution, path depth or filter is refused
+        let other_scene = render_fingerprint(1235, &settings);
+        assert!(matches!(decode(&data, other_scene, &settings, "test.ckpt"), Err(CheckpointError::SceneMismatch { .. })));
+        let deeper = RenderSettings { max_depth: 3, ..RenderSettings { width: 3, height: 2, ..RenderSettings::default() } };
+        assert!(matches!(decode(&data, render_fingerprint(1234, &deeper), &deeper, "test.ckpt"), Err(CheckpointError::SceneMismatch { .. })));
+        let filtered = RenderSettings { filter: Filter::Gaussian, ..RenderSettings { width: 3, height: 2, ..RenderSettings::default() } };
+        assert!(matches!(decode(&data, render_fingerprint(1234, &filtered), &filtered, "test.ckpt"), Err(CheckpointError::SceneMismatch { .. })));
+        assert!(matches!(decode(&data[..data.len() - 1], fingerprint, &settings, "test.ckpt"), Err(CheckpointError::Malformed { .. })));
+    }
+}
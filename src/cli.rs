@@ -0,0 +1,228 @@
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use rusty_render::{Filter, MisHeuristic, RenderSettings, SamplerKind, ToneMapping};
+
+/// Command-line interface. Anything given here overrides the matching setting from the scene file
+#[derive(Parser, Debug)]
+#[command(name = "RustyRender", version, about = "A Smallpaint-style path tracer")]
+pub(crate) struct Arguments {
+    /// Scene file to render
+    #[arg(default_value = "scenes/cornell.toml")]
+    pub(crate) scene: PathBuf,
+
+    /// Image width in pixels
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    pub(crate) width: Option<i32>,
+
+    /// Image height in pixels
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    pub(crate) height: Option<i32>,
+
+    /// Samples per pixel
+    #[arg(short, long, value_parser = clap::value_parser!(i32).range(1..))]
+    pub(crate) samples: Option<i32>,
+
+    /// Maximum number of bounces per path
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    pub(crate) max_depth: Option<i32>,
+
+    /// Output image path
+    #[arg(short, long)]
+    pub(crate) output: Option<String>,
+
+    /// Seed for the random number generator, renders with the same seed are reproducible
+    #[arg(long)]
+    pub(crate) seed: Option<u64>,
+
+    /// Number of worker threads, defaults to one per core
+    #[arg(short = 'j', long)]
+    pub(crate) threads: Option<NonZeroUsize>,
+
+    /// Heuristic for weighting BSDF sampling against light sampling
+    #[arg(long)]
+    pub(crate) mis: Option<MisHeuristic>,
+
+    /// How the random numbers of the samples in a pixel are generated
+    #[arg(long)]
+    pub(crate) sampler: Option<SamplerKind>,
+
+    /// Reconstruction filter splatting samples into the pixels around them
+    #[arg(long)]
+    pub(crate) filter: Option<Filter>,
+
+    /// Radius of the reconstruction filter in pixels, defaults to one suited to the filter
+    #[arg(long)]
+    pub(crate) filter_radius: Option<f64>,
+
+    /// Stop sampling a pixel once the standard error of its luminance is below this fraction of its mean,
+    /// --samples becoming the most any pixel takes
+    #[arg(long)]
+    pub(crate) adaptive_threshold: Option<f64>,
+
+    /// Write an image of the number of samples taken in every pixel to this path
+    #[arg(long)]
+    pub(crate) sample_map: Option<String>,
+
+    /// Exposure adjustment in stops applied before tone mapping 8-bit output
+    #[arg(long, allow_negative_numbers = true)]
+    pub(crate) exposure: Option<f64>,
+
+    /// Tone mapping operator for 8-bit output
+    #[arg(long)]
+    pub(crate) tone_mapping: Option<ToneMapping>,
+
+    /// Render one sample per pixel over the whole image at a time instead of finishing each pixel at once
+    #[arg(long)]
+    pub(crate) progressive: bool,
+
+    /// Save a snapshot of a progressive render every this many passes, implies --progressive
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    pub(crate) snapshot_passes: Option<i32>,
+
+    /// Save a snapshot of a progressive render at least this many seconds apart, implies --progressive
+    #[arg(long)]
+    pub(crate) snapshot_seconds: Option<f64>,
+
+    /// Keep the accumulated samples of a progressive render in this file, updated with every snapshot and at the end,
+    /// implies --progressive
+    #[arg(long)]
+    pub(crate) checkpoint: Option<String>,
+
+    /// Continue the render saved in the checkpoint file, adding passes until --samples are reached,
+    /// implies --progressive
+    #[arg(long)]
+    pub(crate) resume: bool,
+
+    /// Test every object for every ray instead of using the BVH, for validating the acceleration structure
+    #[arg(long)]
+    pub(crate) no_bvh: bool,
+}
+
+impl Arguments {
+    /// Override the settings loaded from the scene file with anything given on the command line
+    pub(crate) fn apply(&self, settings: &mut RenderSettings) {
+        if let Some(width) = self.width {
+            settings.width = width;
+        }
+        if let Some(height) = self.height {
+            settings.height = height;
+        }
+        if let Some(samples) = self.samples {
+            settings.samples = samples;
+        }
+        if let Some(max_depth) = self.max_depth {
+            settings.max_depth = max_depth;
+        }
+        if let Some(output) = &self.output {
+            settings.output = output.clone();
+        }
+        if self.seed.is_some() {
+            settings.seed = self.seed;
+        }
+        if let Some(threads) = self.threads {
+            settings.threads = Some(threads.get());
+        }
+        if let Some(mis) = self.mis {
+            settings.mis_heuristic = mis;
+        }
+        if let Some(sampler) = self.sampler {
+            settings.sampler = sampler;
+        }
+        if let Some(filter) = self.filter {
+            settings.filter = filter;
+        }
+        if self.filter_radius.is_some() {
+            settings.filter_radius = self.filter_radius;
+        }
+        if self.adaptive_threshold.is_some() {
+            settings.adaptive_threshold = self.adaptive_threshold;
+        }
+        if self.sample_map.is_some() {
+            settings.sample_map = self.sample_map.clone();
+        }
+        if let Some(exposure) = self.exposure {
+            settings.exposure = exposure;
+        }
+        if let Some(tone_mapping) = self.tone_mapping {
+            settings.tone_mapping = tone_mapping;
+        }
+        if self.checkpoint.is_some() {
+            settings.checkpoint = self.checkpoint.clone();
+        }
+        if self.progressive || self.snapshot_passes.is_some() || self.snapshot_seconds.is_some() || self.checkpoint.is_some() || self.resume {
+            settings.progressive = true;
+        }
+        if self.snapshot_passes.is_some() {
+            settings.snapshot_passes = self.snapshot_passes;
+        }
+        if self.snapshot_seconds.is_some() {
+            settings.snapshot_seconds = self.snapshot_seconds;
+        }
+        if self.no_bvh {
+            settings.use_bvh = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::error::ErrorKind;
+
+    /// Settings after applying the given command line to the defaults
+    fn settings(arguments: &[&str]) -> RenderSettings {
+        let arguments = Arguments::try_parse_from(["RustyRender"].iter().chain(arguments)).unwrap();
+        let mut settings = RenderSettings::default();
+        arguments.apply(&mut settings);
+        settings
+    }
+
+    #[test]
+    fn overrides_test() {
+        // nothing given keeps what the scene file set
+        let defaults = RenderSettings::default();
+        let kept = settings(&[]);
+        assert_eq!((kept.width, kept.height, kept.samples, kept.output), (defaults.width, defaults.height, defaults.samples, defaults.output));
+        assert!(kept.use_bvh && !kept.progressive);
+
+        let changed = settings(&["scene.toml", "--width", "320", "--height=240", "-s", "64", "--max-depth", "7", "-o", "out.png",
+                                 "--seed", "5", "-j", "3", "--mis", "balance", "--filter-radius", "2.5", "--exposure", "-1.5",
+                                 "--adaptive-threshold", "0.05", "--no-bvh"]);
+        assert_eq!((changed.width, changed.height, changed.samples, changed.max_depth), (320, 240, 64, 7));
+        assert_eq!(changed.output, "out.png");
+        assert_eq!((changed.seed, changed.threads), (Some(5), Some(3)));
+        assert_eq!(changed.mis_heuristic, MisHeuristic::Balance);
+        assert_eq!((changed.filter_radius, changed.adaptive_threshold, changed.exposure), (Some(2.5), Some(0.05), -1.5));
+        assert!(!changed.use_bvh);
+        assert!(!changed.progressive);
+    }
+
+    #[test]
+    fn progressive_implied_test() {
+        for arguments in [&["--progressive"][..], &["--snapshot-passes", "4"], &["--snapshot-seconds", "30"],
+                          &["--checkpoint", "render.ckpt"], &["--resume"]] {
+            assert!(settings(arguments).progressive, "{arguments:?}");
+        }
+        let resumed = settings(&["--resume", "--checkpoint", "render.ckpt", "--snapshot-passes", "4", "--snapshot-seconds", "30"]);
+        assert_eq!(resumed.checkpoint.as_deref(), Some("render.ckpt"));
+        assert_eq!((resumed.snapshot_passes, resumed.snapshot_seconds), (Some(4), Some(30.0)));
+    }
+
+    #[test]
+    fn invalid_arguments_test() {
+        let error = |arguments: &[&str]| Arguments::try_parse_from(["RustyRender"].iter().chain(arguments)).unwrap_err().kind();
+        assert_eq!(error(&["--width", "0"]), ErrorKind::ValueValidation);
+        assert_eq!(error(&["--samples=-4"]), ErrorKind::ValueValidation);
+        assert_eq!(error(&["--snapshot-passes", "0"]), ErrorKind::ValueValidation);
+        assert_eq!(error(&["--threads", "0"]), ErrorKind::ValueValidation);
+        assert_eq!(error(&["--tone-mapping", "sepia"]), ErrorKind::InvalidValue);
+        assert_eq!(error(&["--no-such-flag"]), ErrorKind::UnknownArgument);
+
+        // values clap accepts but the renderer cannot use are refused by RenderSettings::check
+        assert!(settings(&["--snapshot-seconds=-1"]).check().is_err());
+        assert!(settings(&["--filter-radius", "NaN"]).check().is_err());
+    }
+}
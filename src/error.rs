@@ -0,0 +1,87 @@
+use std::fmt;
+
+use crate::checkpoint::CheckpointError;
+use crate::output::OutputError;
+use crate::rendering_equation::RenderError;
+use crate::scene_loader::SceneError;
+use crate::type_structs::MeshError;
+
+// Errors of the renderer
+//
+// Every stage reports failures with its own error type, carrying what that stage knows (file paths, line numbers,
+// object indices). Error gathers them, so code that builds a scene, renders it and writes the image can use ? on all
+// of them and deal with a single type.
+
+/// # Any error produced while building, loading, rendering or saving a scene
+#[derive(Debug)]
+pub enum Error {
+    /// An object given to RenderScene::new has parameters the renderer cannot use, such as a sphere with a negative
+    /// radius, a plane whose normal is not of unit length or a refractive index of zero or less
+    InvalidObject { index: usize, message: String },
+    /// A TriangleMesh could not be built from the given geometry
+    Mesh(MeshError),
+    /// A scene file could not be read or parsed
+    Scene(SceneError),
+    /// A render could not be started
+    Render(RenderError),
+    /// An image could not be encoded or written
+    Output(OutputError),
+    /// A checkpoint could not be saved or loaded
+    Checkpoint(CheckpointError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidObject { index, message } => write!(f, "object {index}: {message}"),
+            Error::Mesh(error) => write!(f, "invalid mesh: {error}"),
+            Error::Scene(error) => write!(f, "could not load scene: {error}"),
+            Error::Render(error) => write!(f, "could not render: {error}"),
+            Error::Output(error) => write!(f, "could not save image: {error}"),
+            Error::Checkpoint(error) => write!(f, "checkpoint: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::InvalidObject { .. } => None,
+            Error::Mesh(error) => Some(error),
+            Error::Scene(error) => Some(error),
+            Error::Render(error) => Some(error),
+            Error::Output(error) => Some(error),
+            Error::Checkpoint(error) => Some(error),
+        }
+    }
+}
+
+impl From<MeshError> for Error {
+    fn from(error: MeshError) -> Self {
+        Error::Mesh(error)
+    }
+}
+
+impl From<SceneError> for Error {
+    fn from(error: SceneError) -> Self {
+        Error::Scene(error)
+    }
+}
+
+impl From<RenderError> for Error {
+    fn from(error: RenderError) -> Self {
+        Error::Render(error)
+    }
+}
+
+impl From<OutputError> for Error {
+    fn from(error: OutputError) -> Self {
+        Error::Output(error)
+    }
+}
+
+impl From<CheckpointError> for Error {
+    fn from(error: CheckpointError) -> Self {
+        Error::Checkpoint(error)
+    }
+}
@@ -0,0 +1,97 @@
+use crate::type_structs::DiffuseColour;
+
+/// # Image of width x height pixels stored row by row
+/// Row 0 is the top of the image and column 0 its left edge. Every framebuffer of the renderer is a Film, so pixel
+/// (column, row) is found the same way everywhere
+#[derive(Clone, Debug)]
+pub struct Film<T> {
+    width: i32,
+    height: i32,
+    pixels: Vec<T>,
+}
+
+/// # Linear radiance of every pixel of a rendered image
+pub type Image = Film<DiffuseColour>;
+
+/// Number of pixels in a film of width x height, None for negative sizes and films too large to index with an i32
+pub(crate) fn pixel_count(width: i32, height: i32) -> Option<usize> {
+    let count = usize::try_from(width).ok()?.checked_mul(usize::try_from(height).ok()?)?;
+    (count <= i32::MAX as usize).then_some(count)
+}
+
+impl<T: Clone> Film<T> {
+    /// A film with every pixel set to `value`
+    /// # Panics
+    /// If width or height is negative, or the film would have more than i32::MAX pixels
+    pub fn new(width: i32, height: i32, value: T) -> Film<T> {
+        let count = pixel_count(width, height).unwrap_or_else(|| panic!("a film cannot be {width}x{height} pixels"));
+        Film {
+            width,
+            height,
+            pixels: vec![value; count],
+        }
+    }
+}
+
+impl<T> Film<T> {
+    /// A film made from pixels given row by row, None if there are not width x height of them
+    pub fn from_pixels(width: i32, height: i32, pixels: Vec<T>) -> Option<Film<T>> {
+        (pixel_count(width, height) == Some(pixels.len())).then_some(Film { width, height, pixels })
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    /// Position of pixel (column, row) in the row by row storage
+    pub fn index(&self, column: i32, row: i32) -> usize {
+        debug_assert!((0..self.width).contains(&column) && (0..self.height).contains(&row));
+        (row * self.width + column) as usize
+    }
+
+    pub fn get(&self, column: i32, row: i32) -> &T {
+        &self.pixels[self.index(column, row)]
+    }
+
+    pub fn get_mut(&mut self, column: i32, row: i32) -> &mut T {
+        let index = self.index(column, row);
+        &mut self.pixels[index]
+    }
+
+    /// Every pixel, row by row
+    pub fn pixels(&self) -> &[T] {
+        &self.pixels
+    }
+
+    /// A film of the same size with `function` applied to every pixel
+    pub fn map<U, F: FnMut(&T) -> U>(&self, function: F) -> Film<U> {
+        Film {
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels.iter().map(function).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn film_test() {
+        let mut film = Film::new(3, 2, 0);
+        *film.get_mut(2, 0) = 5;
+        *film.get_mut(0, 1) = 7;
+        // row by row, the last pixel of the first row comes just before the first of the second
+        assert_eq!(film.pixels(), &[0, 0, 5, 7, 0, 0]);
+        assert_eq!(*film.map(|value| value*2).get(0, 1), 14);
+        assert!(Film::from_pixels(3, 2, vec![0; 5]).is_none());
+        // 65536 x 65536 wraps to zero in an i32, it must not pass for an empty film
+        assert!(Film::<i32>::from_pixels(65536, 65536, Vec::new()).is_none());
+        assert_eq!(pixel_count(-1, 2), None);
+    }
+}
@@ -0,0 +1,112 @@
+use std::f64::consts::PI;
+
+// Reconstruction filters
+//
+// Every sample is taken at a random point of its pixel and splatted into all pixels whose centres lie within the
+// filter radius, weighted by the filter at its offset from the centre. A pixel's value is the weighted sum of the
+// radiance splatted into it divided by the sum of the weights. Filters are separable, the product of a 1D filter
+// along each axis, and the radius is in pixels.
+
+/// # Filter weighting the samples around a pixel centre
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum Filter {
+    /// Every sample within the radius counts the same; with radius 0.5 a pixel averages its own samples
+    Box,
+    /// Weight falling linearly to 0 at the radius
+    Tent,
+    /// Gaussian with a standard deviation of a third of the radius, shifted to reach 0 at the radius
+    Gaussian,
+    /// Mitchell-Netravali cubic with B = C = 1/3, stretched over the radius
+    Mitchell,
+    /// Lanczos windowed sinc, with as many lobes as the radius has pixels
+    Lanczos,
+}
+
+/// sin(pi x)/(pi x)
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.0
+    }else {
+        (PI*x).sin()/(PI*x)
+    }
+}
+
+impl Filter {
+    /// Radius used when the render settings do not give one
+    pub fn default_radius(&self) -> f64 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.0,
+            Filter::Lanczos => 3.0,
+        }
+    }
+
+    /// Weight of a sample at `offset` pixels from the centre along one axis, the offset lies within the radius
+    fn evaluate_1d(&self, offset: f64, radius: f64) -> f64 {
+        let x = offset.abs();
+        match self {
+            Filter::Box => 1.0,
+            Filter::Tent => (1.0 - x/radius).max(0.0),
+            Filter::Gaussian => {
+                let sigma = radius/3.0;
+                let gaussian = |x: f64| (-x*x/(2.0*sigma*sigma)).exp();
+                (gaussian(x) - gaussian(radius)).max(0.0)
+            }
+            Filter::Mitchell => {
+                let (b, c) = (1.0/3.0, 1.0/3.0);
+                // the cubic is defined on [-2, 2]
+                let x = 2.0*x/radius;
+                if x < 1.0 {
+                    ((12.0 - 9.0*b - 6.0*c)*x*x*x + (-18.0 + 12.0*b + 6.0*c)*x*x + (6.0 - 2.0*b))/6.0
+                }else if x < 2.0 {
+                    ((-b - 6.0*c)*x*x*x + (6.0*b + 30.0*c)*x*x + (-12.0*b - 48.0*c)*x + (8.0*b + 24.0*c))/6.0
+                }else {
+                    0.0
+                }
+            }
+            Filter::Lanczos => sinc(x)*sinc(x/radius),
+        }
+    }
+
+    /// Weight of a sample at (dx, dy) pixels from a pixel centre, 0 outside [-radius, radius) along either axis.
+    /// The half-open range makes a box of radius 0.5 give every sample to exactly one pixel.
+    /// Mitchell and Lanczos have negative lobes, so weights can be negative
+    pub fn evaluate(&self, dx: f64, dy: f64, radius: f64) -> f64 {
+        if dx < -radius || dx >= radius || dy < -radius || dy >= radius {
+            return 0.0;
+        }
+        self.evaluate_1d(dx, radius)*self.evaluate_1d(dy, radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_test() {
+        for filter in [Filter::Box, Filter::Tent, Filter::Gaussian, Filter::Mitchell, Filter::Lanczos] {
+            let radius = filter.default_radius();
+            assert_eq!(filter.evaluate(radius, 0.0, radius), 0.0, "{filter:?}");
+            assert_eq!(filter.evaluate(0.0, -radius - 0.01, radius), 0.0, "{filter:?}");
+            // peaked at the centre and symmetric
+            let centre = filter.evaluate(0.0, 0.0, radius);
+            assert!(centre > 0.0, "{filter:?}");
+            for offset in [0.1, 0.3, 0.45] {
+                let weight = filter.evaluate(offset*radius, 0.0, radius);
+                assert!(weight <= centre, "{filter:?}");
+                assert!((weight - filter.evaluate(-offset*radius, 0.0, radius)).abs() < 1e-12, "{filter:?}");
+            }
+            // the edge of the support carries next to no weight, except for the box
+            if filter != Filter::Box {
+                assert!(filter.evaluate(0.999*radius, 0.0, radius).abs() < 0.01*centre, "{filter:?}");
+            }
+        }
+        // Mitchell-Netravali with B = C = 1/3 has a small negative lobe
+        assert!(Filter::Mitchell.evaluate(1.5, 0.0, 2.0) < 0.0);
+        assert!((Filter::Mitchell.evaluate(0.0, 0.0, 2.0) - 16.0/18.0*16.0/18.0).abs() < 1e-12);
+        assert_eq!(Filter::Tent.evaluate(0.5, 0.5, 1.0), 0.25);
+    }
+}
@@ -0,0 +1,55 @@
+//! # RustyRender
+//! A Smallpaint-style path tracer. Build a [`RenderScene`] from objects and a [`Camera`], or load one from a TOML
+//! scene file with [`scene_loader::load_scene`], and turn it into an [`Image`] of linear radiance with [`render`]:
+//!
+//! ```
+//! use rusty_render::{render, DiffuseColour, Error, Material, RenderScene, RenderSettings, Sphere, Vec3D};
+//!
+//! # fn main() -> Result<(), Error> {
+//! let scene = RenderScene::new(vec![
+//!     Box::new(Sphere {
+//!         center: Vec3D {x: 0.0, y: 0.0, z: -3.0},
+//!         radius: 1.0,
+//!         material: Material::Emissive { colour: DiffuseColour {r: 12.0, g: 12.0, b: 12.0}, strength: 1.0 },
+//!     }),
+//! ])?;
+//! let settings = RenderSettings { width: 32, height: 16, samples: 1, ..RenderSettings::default() };
+//! let image = render(&scene, &settings)?;
+//! assert_eq!((image.width(), image.height()), (32, 16));
+//! assert!(image.get(16, 8).r > 0.0);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! With [`RenderSettings::use_bvh`] set, as it is by default, the render builds the scene's BVH if it has none yet;
+//! call [`RenderScene::build_bvh`] again after changing its objects. [`output::save_image`] writes an image to disk,
+//! and [`rendering_equation::render_progressive`] builds one up a pass at a time. Every stage has its own error type,
+//! all of which convert into [`Error`].
+
+pub mod camera;
+pub mod checkpoint;
+pub mod error;
+pub mod film;
+pub mod filter;
+pub mod material;
+pub mod obj_loader;
+pub mod output;
+pub mod rendering_equation;
+pub mod sampler;
+pub mod scene_loader;
+pub mod tonemapping;
+pub mod type_structs;
+pub mod validation;
+mod bvh;
+mod random;
+
+pub use camera::Camera;
+pub use error::Error;
+pub use film::{Film, Image};
+pub use filter::Filter;
+pub use material::Material;
+pub use rendering_equation::{render, MisHeuristic, RenderError, RenderSettings};
+pub use sampler::SamplerKind;
+pub use tonemapping::ToneMapping;
+pub use type_structs::{AxisAlignedBox, DiffuseColour, Disk, MeshError, Plane, Quad, Ray, RenderScene, SceneObject, Sphere, Triangle, TriangleMesh, Vec3D};
+pub use validation::{Severity, ValidationIssue};
@@ -1,63 +1,125 @@
-use arrayvec::ArrayVec;
-use image::{ImageBuffer, Rgb};
-use crate::rendering_equation::{HEIGHT, simulate_per_pixel, WIDTH};
-use crate::type_structs::{DiffuseColour, Plane, RenderScene, Sphere, Vec3D};
+use std::process::ExitCode;
 
-pub mod type_structs;
-mod rendering_equation;
+use clap::Parser;
+use rusty_render::checkpoint::{load_checkpoint, render_fingerprint, save_checkpoint};
+use rusty_render::output::{save_image, save_sample_map};
+use rusty_render::rendering_equation::{render_image, render_progressive, Accumulation};
+use rusty_render::scene_loader::load_scene;
+use rusty_render::Severity;
 
-static SAMPLES: i32 = 8;
-fn build_diffuse_colour() -> DiffuseColour{
-    DiffuseColour {
-        r: 0.0,
-        g: 0.0,
-        b: 0.0
-    }
-}
+use crate::cli::Arguments;
 
-fn main() {
+mod cli;
+
+fn main() -> ExitCode {
+    let arguments = Arguments::parse();
     println!("Starting");
 
-    // create scene
-    let mut scene: RenderScene = RenderScene {
-        objects_list: Vec::new()
+    // load the scene and apply any overrides from the command line
+    let loaded = match load_scene(&arguments.scene) {
+        Ok(loaded) => loaded,
+        Err(error) => {
+            eprintln!("Could not load scene: {error}");
+            return ExitCode::FAILURE;
+        }
     };
-    // add objects to the scene
-    // spheres
-    scene.objects_list.push(Box::new(Sphere { center: Vec3D { x: -0.75, y: -1.45, z: -4.4 }, radius: 1.05, colour: DiffuseColour { r: 4.0, g: 8.0, b: 4.0 }, material_type: 2, emission: 0.0, refractive_index: 0.0 }));
-    scene.objects_list.push(Box::new(Sphere { center: Vec3D { x: 2.0, y: -2.05, z: -3.7 }, radius: 0.5, colour: DiffuseColour { r: 10.0, g: 10.0, b: 1.0 }, material_type: 1, emission: 0.0, refractive_index: 1.51 }));
-    scene.objects_list.push(Box::new(Sphere { center: Vec3D { x: -1.75, y: -1.95, z: -3.1 }, radius: 0.6, colour: DiffuseColour { r: 4.0, g: 4.0, b: 12.0 }, material_type: 1, emission: 0.0, refractive_index: 0.0 }));
-    scene.objects_list.push(Box::new(Sphere { center: Vec3D { x: 0.0, y: 1.9, z: -3.0 }, radius: 0.5, colour: DiffuseColour { r: 12.0, g: 12.0, b: 12.0 }, material_type: 1, emission: 10000.0, refractive_index: 0.0 }));
-
-    // planes
-    scene.objects_list.push(Box::new(Plane {normal: Vec3D {x: 0.0, y: 1.0, z: 0.0}, distance_to_origin: 2.5, colour: DiffuseColour {r: 6.0, g: 6.0, b: 6.0}, material_type: 1, emission: 0.0, refractive_index: 0.0}));
-    scene.objects_list.push(Box::new(Plane {normal: Vec3D {x: 0.0, y: 0.0, z: 1.0}, distance_to_origin: 5.5, colour: DiffuseColour {r: 6.0, g: 6.0, b: 6.0}, material_type: 1, emission: 0.0, refractive_index: 0.0}));
-    scene.objects_list.push(Box::new(Plane {normal: Vec3D {x: 1.0, y: 0.0, z: 0.0}, distance_to_origin: 2.75, colour: DiffuseColour {r: 10.0, g: 2.0, b: 2.0}, material_type: 1, emission: 0.0, refractive_index: 0.0}));
-    scene.objects_list.push(Box::new(Plane {normal: Vec3D {x: -1.0, y: 0.0, z: 0.0}, distance_to_origin: 2.75, colour: DiffuseColour {r: 2.0, g: 10.0, b: 2.0}, material_type: 1, emission: 0.0, refractive_index: 0.0}));
-    scene.objects_list.push(Box::new(Plane {normal: Vec3D {x: 0.0, y: -1.0, z: 0.0}, distance_to_origin: 3.0, colour: DiffuseColour {r: 6.0, g: 6.0, b: 6.0}, material_type: 1, emission: 0.0, refractive_index: 0.0}));
-    scene.objects_list.push(Box::new(Plane {normal: Vec3D {x: 0.0, y: 0.0, z: -1.0}, distance_to_origin: 0.5, colour: DiffuseColour {r: 6.0, g: 6.0, b: 6.0}, material_type: 1, emission: 0.0, refractive_index: 0.0}));
-
-    // create and simulate pixels
-    // create pixels array and initialize all of them
-    let mut image_pixels = vec![build_diffuse_colour(); (WIDTH * HEIGHT) as usize];
+    let scene = loaded.scene;
+    // warnings are reported but rendered anyway, errors stop here
+    let issues = scene.validate();
+    for issue in &issues {
+        let object = issue.object.map_or_else(String::new, |index| format!("{}: ", loaded.object_names[index]));
+        eprintln!("{}: {}: {object}{}", arguments.scene.display(), issue.severity, issue.message);
+    }
+    if issues.iter().any(|issue| issue.severity == Severity::Error) {
+        return ExitCode::FAILURE;
+    }
+    let mut settings = loaded.settings;
+    arguments.apply(&mut settings);
+    if let Err(error) = settings.check() {
+        eprintln!("Invalid render settings: {error}");
+        return ExitCode::FAILURE;
+    }
+    println!("Rendering {}x{} at {} samples per pixel on {} thread(s)", settings.width, settings.height, settings.samples,
+             settings.threads.map_or_else(|| "all".to_string(), |threads| threads.to_string()));
 
-    // main loop
-    for i in 0..WIDTH {
-        for j in 0..HEIGHT {
-            simulate_per_pixel(i,j,&scene,SAMPLES,&mut image_pixels);
+    // simulate all pixels, tiles are rendered in parallel. Progressive renders overwrite the output with snapshots
+    // along the way, so they can be stopped once the image is good enough, and can keep a checkpoint to resume from
+    let rendered = if settings.progressive {
+        let fingerprint = render_fingerprint(loaded.fingerprint, &settings);
+        let mut accumulation = if arguments.resume {
+            let Some(path) = &settings.checkpoint else {
+                eprintln!("Could not resume: no checkpoint file given");
+                return ExitCode::FAILURE;
+            };
+            match load_checkpoint(fingerprint, &settings, path) {
+                Ok(accumulation) => {
+                    // a resumed render keeps drawing samples from the checkpoint's seed, so a different one cannot apply
+                    if let Some(seed) = arguments.seed.filter(|seed| *seed != accumulation.seed) {
+                        eprintln!("Could not resume: the checkpoint was rendered with seed {}, not --seed {seed}", accumulation.seed);
+                        return ExitCode::FAILURE;
+                    }
+                    println!("Resuming after {} passes from: {path}", accumulation.passes);
+                    accumulation
+                }
+                Err(error) => {
+                    eprintln!("Could not resume: {error}");
+                    return ExitCode::FAILURE;
+                }
+            }
+        }else {
+            // the seed is stored in checkpoints, so a resumed render carries on the same sequence of samples
+            Accumulation::new(&settings, settings.seed.unwrap_or_else(rand::random))
+        };
+        let save = |accumulation: &Accumulation| {
+            if let Some(path) = &settings.checkpoint {
+                if let Err(error) = save_checkpoint(accumulation, fingerprint, &settings, path) {
+                    eprintln!("Could not save checkpoint: {error}");
+                }
+            }
+        };
+        render_progressive(&scene, &settings, &mut accumulation, |so_far| {
+            match save_image(&so_far.image(), &settings, &settings.output) {
+                Ok(_) => println!("Snapshot after {} passes saved as: {}", so_far.passes, settings.output),
+                Err(error) => eprintln!("Could not save snapshot: {error}"),
+            }
+            save(so_far);
+        }).map(|_| {
+            save(&accumulation);
+            accumulation
+        })
+    }else {
+        render_image(&scene, &settings)
+    };
+    let accumulation = match rendered {
+        Ok(accumulation) => accumulation,
+        Err(error) => {
+            eprintln!("Could not render: {error}");
+            return ExitCode::FAILURE;
+        }
+    };
 
+    // write all pixels, .exr and .pfm outputs keep the linear radiance
+    match save_image(&accumulation.image(), &settings, &settings.output) {
+        Ok(_) => {println!("Saved as: {}", settings.output)}
+        Err(error) => {
+            eprintln!("Could not save: {error}");
+            return ExitCode::FAILURE;
         }
     }
-
-    let mut buff: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::new(WIDTH as u32, HEIGHT as u32);
-    // print all pixels
-    for (i,pixel) in image_pixels.iter().enumerate() {
-        buff.put_pixel((i as i32 / WIDTH) as u32,(i as i32 % WIDTH) as u32 , Rgb([u8::min(pixel.r as u8, 255),u8::min(pixel.g as u8, 255),u8::min(pixel.b as u8, 255)]));
-        // println!("{},{},{}", i.r, i.g,i.b);
+    if settings.adaptive_threshold.is_some() {
+        let sample_counts = accumulation.sample_counts();
+        let total: u64 = sample_counts.pixels().iter().map(|count| *count as u64).sum();
+        println!("Adaptive sampling took {:.1} samples per pixel on average", total as f64/sample_counts.pixels().len() as f64);
     }
-    match buff.save("Converged.png") {
-        Ok(_) => {println!("Saved as: Converged.png")}
-        Err(_) => {println!("Something went wrong")}
+    if let Some(path) = &settings.sample_map {
+        match save_sample_map(&accumulation.sample_counts(), &settings, path) {
+            Ok(_) => println!("Sample map saved as: {path}"),
+            Err(error) => {
+                eprintln!("Could not save sample map: {error}");
+                return ExitCode::FAILURE;
+            }
+        }
     }
     println!("finished");
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,337 @@
+use std::f64::consts::PI;
+
+use crate::rendering_equation::hemisphere;
+use crate::sampler::Sampler;
+use crate::type_structs::{DiffuseColour, Vec3D};
+use crate::validation::Severity;
+
+/// Colours are given on a 0-12 scale throughout the renderer, this maps them to 0-1 reflectance
+pub static COLOUR_SCALE: f64 = 12.0;
+
+/// # Surface material of a scene object
+/// Each variant only carries the settings that mean something for it. New scattering models are added as a
+/// variant here together with their arms in sample, eval and pdf; trace only talks to those methods
+#[derive(Clone, Copy, Debug)]
+pub enum Material {
+    /// Lambertian reflector with reflectance colour / 12
+    Diffuse { colour: DiffuseColour },
+    /// Perfect mirror
+    Specular,
+    /// Blurry mirror with reflectance colour / 12, using a normalized Phong lobe around the mirror direction.
+    /// Higher exponents give sharper reflections
+    Glossy { colour: DiffuseColour, exponent: f64 },
+    /// Smooth dielectric such as glass, reflecting or refracting according to Schlick's Fresnel approximation
+    Refractive { refractive_index: f64 },
+    /// Light source emitting colour / 12 * strength, it does not reflect any light
+    Emissive { colour: DiffuseColour, strength: f64 },
+}
+
+/// # A direction chosen by Material::sample
+/// `weight` is the BSDF times the cosine divided by the pdf, the factor incoming light along `direction` is
+/// multiplied by. `pdf` is the solid angle density of the direction, meaningless (and 0) for specular samples
+pub(crate) struct BsdfSample {
+    pub(crate) direction: Vec3D,
+    pub(crate) weight: DiffuseColour,
+    pub(crate) pdf: f64,
+    pub(crate) specular: bool,
+}
+
+/// Rotate a direction sampled around +z into the frame of the given normal
+fn to_world(sample_direction: &Vec3D, normal: &Vec3D) -> Vec3D {
+    let mut rotation_x = Vec3D {x: 0.0, y: 0.0, z: 0.0};
+    let mut rotation_y = Vec3D {x: 0.0, y: 0.0, z: 0.0};
+    normal.orthonormal_system(&mut rotation_x,&mut rotation_y);
+    Vec3D {
+        x: Vec3D {x: rotation_x.x, y: rotation_y.x, z: normal.x}.dot(sample_direction),
+        y: Vec3D {x: rotation_x.y, y: rotation_y.y, z: normal.y}.dot(sample_direction),
+        z: Vec3D {x: rotation_x.z, y: rotation_y.z, z: normal.z}.dot(sample_direction),
+    }
+}
+
+/// Mirror a direction about the normal
+fn reflect(direction: &Vec3D, normal: &Vec3D) -> Vec3D {
+    let mut reflected = direction.subtract(&normal.scalar_mult(direction.dot(normal)*2.0));
+    reflected.norm();
+    reflected
+}
+
+/// The normal flipped, if needed, to lie on the side the ray arrives from
+fn facing_normal(direction: &Vec3D, normal: &Vec3D) -> Vec3D {
+    if normal.dot(direction) > 0.0 {
+        normal.scalar_mult(-1.0)
+    }else {
+        *normal
+    }
+}
+
+impl Material {
+    /// Check that the material's parameters can be rendered, Err says what is wrong
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            Material::Refractive { refractive_index } if !(*refractive_index > 0.0 && refractive_index.is_finite()) => {
+                Err(format!("refractive index {refractive_index} must be a finite number greater than zero"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Problems with the material's settings for RenderScene::validate, beyond those check refuses
+    pub fn validate(&self) -> Vec<(Severity, String)> {
+        let mut issues = Vec::new();
+        let mut colour_issues = |colour: &DiffuseColour, reflects: bool| {
+            let channels = [colour.r, colour.g, colour.b];
+            if !channels.iter().all(|channel| channel.is_finite()) {
+                issues.push((Severity::Error, "material colour is NaN or infinite".to_string()));
+            }else if channels.iter().any(|channel| *channel < 0.0) {
+                issues.push((Severity::Error, "material colour must not be negative".to_string()));
+            }else if reflects && channels.iter().any(|channel| *channel > COLOUR_SCALE) {
+                issues.push((Severity::Warning, format!("material colour above {COLOUR_SCALE} reflects more light than it receives")));
+            }
+        };
+        match self {
+            Material::Diffuse { colour } => colour_issues(colour, true),
+            Material::Specular => {}
+            Material::Glossy { colour, exponent } => {
+                colour_issues(colour, true);
+                if !(exponent.is_finite() && *exponent >= 0.0) {
+                    issues.push((Severity::Error, format!("glossy exponent {exponent} must be a finite number of at least 0")));
+                }
+            }
+            Material::Refractive { refractive_index } => {
+                if !refractive_index.is_finite() {
+                    issues.push((Severity::Error, "refractive index is NaN or infinite".to_string()));
+                }else if *refractive_index == 1.0 {
+                    issues.push((Severity::Warning, "refractive index 1 neither bends nor reflects light, the object is invisible".to_string()));
+                }
+            }
+            Material::Emissive { colour, strength } => {
+                colour_issues(colour, false);
+                if !strength.is_finite() || *strength < 0.0 {
+                    issues.push((Severity::Error, format!("emission strength {strength} must be a finite number of at least 0")));
+                }else if *strength == 0.0 || colour.r + colour.g + colour.b == 0.0 {
+                    issues.push((Severity::Warning, "emissive material emits no light".to_string()));
+                }
+            }
+        }
+        issues
+    }
+
+    /// Radiance emitted by the surface
+    pub(crate) fn emitted(&self) -> DiffuseColour {
+        match self {
+            Material::Emissive { colour, strength } => DiffuseColour {
+                r: colour.r/COLOUR_SCALE*strength,
+                g: colour.g/COLOUR_SCALE*strength,
+                b: colour.b/COLOUR_SCALE*strength,
+            },
+            _ => DiffuseColour {r: 0.0, g: 0.0, b: 0.0},
+        }
+    }
+
+    /// Choose a direction to continue a path arriving along `direction` at a surface with the given normal.
+    /// None if the material does not scatter light
+    pub(crate) fn sample<S: Sampler>(&self, direction: &Vec3D, normal: &Vec3D, sampler: &mut S) -> Option<BsdfSample> {
+        match self {
+            Material::Diffuse { colour } => {
+                // cosine weighted, so the cosine and pdf cancel and only the reflectance remains.
+                // sample the hemisphere on the side the ray arrived from, open surfaces such as triangles can be hit from behind
+                let normal = facing_normal(direction, normal);
+                let sampled = to_world(&hemisphere(sampler), &normal);
+                Some(BsdfSample {
+                    direction: sampled,
+                    weight: DiffuseColour {r: colour.r/COLOUR_SCALE, g: colour.g/COLOUR_SCALE, b: colour.b/COLOUR_SCALE},
+                    pdf: sampled.dot(&normal).max(0.0)/PI,
+                    specular: false,
+                })
+            }
+            Material::Glossy { colour, exponent } => {
+                let normal = facing_normal(direction, normal);
+                let lobe_axis = reflect(direction, &normal);
+                // sample cos^exponent around the mirror direction
+                let (rand, rand2) = sampler.get_2d();
+                let cosine_lobe = rand.powf(1.0/(exponent + 1.0));
+                let sine_lobe = (1.0 - cosine_lobe*cosine_lobe).max(0.0).sqrt();
+                let angle = 2.0*PI*rand2;
+                let sampled = to_world(&Vec3D {x: sine_lobe*angle.cos(), y: sine_lobe*angle.sin(), z: cosine_lobe}, &lobe_axis);
+                let cosine = sampled.dot(&normal);
+                // parts of the lobe below the surface carry no light
+                let factor = if cosine > 0.0 { (exponent + 2.0)/(exponent + 1.0)*cosine } else { 0.0 };
+                Some(BsdfSample {
+                    direction: sampled,
+                    weight: DiffuseColour {r: colour.r/COLOUR_SCALE*factor, g: colour.g/COLOUR_SCALE*factor, b: colour.b/COLOUR_SCALE*factor},
+                    pdf: (exponent + 1.0)/(2.0*PI)*cosine_lobe.powf(*exponent),
+                    specular: false,
+                })
+            }
+            Material::Specular => Some(BsdfSample {
+                direction: reflect(direction, normal),
+                weight: DiffuseColour {r: 1.0, g: 1.0, b: 1.0},
+                pdf: 0.0,
+                specular: true,
+            }),
+            Material::Refractive { refractive_index } => {
+                let ratio = ((1.0-refractive_index)/(1.0+refractive_index)).powi(2);
+                // relative index of refraction, inverted when leaving the medium
+                let mut r_index = 1.0/refractive_index;
+                if normal.dot(direction) > 0.0 {
+                    r_index = *refractive_index;
+                }
+                let normal = facing_normal(direction, normal);
+
+                let cosine_direction_1 = -normal.dot(direction);
+                let cosine_direction_2 = 1.0 - (r_index*r_index*(1.0-(cosine_direction_1*cosine_direction_1)));
+                // Schlick approximation
+                let fresnel_probability_factor = ratio + (1.0-ratio)*((1.0-cosine_direction_1).powi(5));
+
+                // reflection and refraction are chosen with their Fresnel probabilities, so the weight is 1 either way
+                let direction = if cosine_direction_2 > 0.0 && sampler.get_1d() > fresnel_probability_factor {
+                    let mut refracted = direction.scalar_mult(r_index).add(&normal.scalar_mult(r_index*cosine_direction_1-cosine_direction_2.sqrt()));
+                    refracted.norm();
+                    refracted
+                }else {
+                    reflect(direction, &normal)
+                };
+                Some(BsdfSample {
+                    direction,
+                    weight: DiffuseColour {r: 1.0, g: 1.0, b: 1.0},
+                    pdf: 0.0,
+                    specular: true,
+                })
+            }
+            Material::Emissive { .. } => None,
+        }
+    }
+
+    /// Value of the BSDF for light arriving from `sampled` and leaving back along `direction`.
+    /// Always 0 for specular materials, whose scattering can only be sampled
+    pub(crate) fn eval(&self, direction: &Vec3D, sampled: &Vec3D, normal: &Vec3D) -> DiffuseColour {
+        match self {
+            Material::Diffuse { colour } => {
+                let normal = facing_normal(direction, normal);
+                if sampled.dot(&normal) <= 0.0 {
+                    return DiffuseColour {r: 0.0, g: 0.0, b: 0.0};
+                }
+                DiffuseColour {r: colour.r/COLOUR_SCALE/PI, g: colour.g/COLOUR_SCALE/PI, b: colour.b/COLOUR_SCALE/PI}
+            }
+            Material::Glossy { colour, exponent } => {
+                let normal = facing_normal(direction, normal);
+                if sampled.dot(&normal) <= 0.0 {
+                    return DiffuseColour {r: 0.0, g: 0.0, b: 0.0};
+                }
+                let lobe = (exponent + 2.0)/(2.0*PI)*reflect(direction, &normal).dot(sampled).max(0.0).powf(*exponent);
+                DiffuseColour {r: colour.r/COLOUR_SCALE*lobe, g: colour.g/COLOUR_SCALE*lobe, b: colour.b/COLOUR_SCALE*lobe}
+            }
+            _ => DiffuseColour {r: 0.0, g: 0.0, b: 0.0},
+        }
+    }
+
+    /// Solid angle density with which sample chooses `sampled` for a path arriving along `direction`
+    pub(crate) fn pdf(&self, direction: &Vec3D, sampled: &Vec3D, normal: &Vec3D) -> f64 {
+        match self {
+            Material::Diffuse { .. } => {
+                let normal = facing_normal(direction, normal);
+                sampled.dot(&normal).max(0.0)/PI
+            }
+            Material::Glossy { exponent, .. } => {
+                let normal = facing_normal(direction, normal);
+                (exponent + 1.0)/(2.0*PI)*reflect(direction, &normal).dot(sampled).max(0.0).powf(*exponent)
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Pcg32;
+    use crate::sampler::IndependentSampler;
+
+    #[test]
+    fn diffuse_sample_test() {
+        let mut sampler = IndependentSampler::new(Pcg32::new(1, 2));
+        let material = Material::Diffuse { colour: DiffuseColour {r: 12.0, g: 6.0, b: 0.0} };
+        let normal = Vec3D {x: 0.0, y: 1.0, z: 0.0};
+        // arriving from below the surface, samples go to the lower side
+        let direction = Vec3D {x: 0.0, y: 1.0, z: 0.0};
+        for _ in 0..100 {
+            let sample = material.sample(&direction, &normal, &mut sampler).unwrap();
+            assert!(sample.direction.y <= 0.0);
+            assert!((sample.direction.length() - 1.0).abs() < 1e-9);
+            assert_eq!(sample.weight.r, 1.0);
+            assert_eq!(sample.weight.g, 0.5);
+            // the weight is the BSDF times the cosine over the pdf
+            let f = material.eval(&direction, &sample.direction, &normal);
+            let pdf = material.pdf(&direction, &sample.direction, &normal);
+            assert!((pdf - sample.pdf).abs() < 1e-12);
+            if pdf > 1e-9 {
+                assert!((f.r*sample.direction.y.abs()/pdf - sample.weight.r).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn glossy_sample_test() {
+        let mut sampler = IndependentSampler::new(Pcg32::new(1, 2));
+        let material = Material::Glossy { colour: DiffuseColour {r: 12.0, g: 12.0, b: 12.0}, exponent: 50.0 };
+        let normal = Vec3D {x: 0.0, y: 1.0, z: 0.0};
+        let mut direction = Vec3D {x: 1.0, y: -1.0, z: 0.0};
+        direction.norm();
+        for _ in 0..100 {
+            let sample = material.sample(&direction, &normal, &mut sampler).unwrap();
+            assert!(!sample.specular);
+            // the sample agrees with eval and pdf, and stays close to the mirror direction
+            let pdf = material.pdf(&direction, &sample.direction, &normal);
+            assert!((pdf - sample.pdf).abs() < 1e-9*pdf.max(1.0));
+            assert!(sample.direction.dot(&Vec3D {x: direction.x, y: -direction.y, z: 0.0}) > 0.5);
+            let f = material.eval(&direction, &sample.direction, &normal);
+            assert!((f.r*sample.direction.y/pdf - sample.weight.r).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn specular_sample_test() {
+        let mut sampler = IndependentSampler::new(Pcg32::new(1, 2));
+        let mut direction = Vec3D {x: 1.0, y: -1.0, z: 0.0};
+        direction.norm();
+        let normal = Vec3D {x: 0.0, y: 1.0, z: 0.0};
+        let sample = Material::Specular.sample(&direction, &normal, &mut sampler).unwrap();
+        assert!(sample.specular);
+        assert!((sample.direction.x - direction.x).abs() < 1e-12);
+        assert!((sample.direction.y + direction.y).abs() < 1e-12);
+    }
+
+    #[test]
+    fn refractive_sample_test() {
+        let mut sampler = IndependentSampler::new(Pcg32::new(1, 2));
+        let material = Material::Refractive { refractive_index: 1.5 };
+        let normal = Vec3D {x: 0.0, y: 1.0, z: 0.0};
+        let direction = Vec3D {x: 0.0, y: -1.0, z: 0.0};
+        // at normal incidence about 4% of the light is reflected, the rest passes straight through
+        let mut transmitted = 0;
+        for _ in 0..1000 {
+            let sample = material.sample(&direction, &normal, &mut sampler).unwrap();
+            if sample.direction.y < 0.0 {
+                transmitted += 1;
+                assert!((sample.direction.y + 1.0).abs() < 1e-9);
+            }
+        }
+        assert!(transmitted > 900 && transmitted < 1000);
+
+        // grazing light leaving the glass is totally internally reflected
+        let mut grazing = Vec3D {x: 1.0, y: 0.2, z: 0.0};
+        grazing.norm();
+        let sample = material.sample(&grazing, &normal, &mut sampler).unwrap();
+        assert!(sample.direction.y < 0.0);
+    }
+
+    #[test]
+    fn emissive_test() {
+        let mut sampler = IndependentSampler::new(Pcg32::new(1, 2));
+        let material = Material::Emissive { colour: DiffuseColour {r: 12.0, g: 12.0, b: 6.0}, strength: 2.0 };
+        assert_eq!(material.emitted().r, 2.0);
+        assert_eq!(material.emitted().b, 1.0);
+        let normal = Vec3D {x: 0.0, y: 1.0, z: 0.0};
+        assert!(material.sample(&normal.scalar_mult(-1.0), &normal, &mut sampler).is_none());
+    }
+}
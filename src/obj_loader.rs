@@ -0,0 +1,453 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::checkpoint::{fnv1a, FNV_OFFSET};
+use crate::material::{Material, COLOUR_SCALE};
+use crate::type_structs::{DiffuseColour, MeshError, TriangleMesh, Vec3D};
+
+// Wavefront OBJ/MTL import
+//
+// Supported OBJ statements are v, vt, vn, f (any polygon, triangulated as a fan, with v, v/vt, v//vn and v/vt/vn
+// references, negative indices count back from the end), usemtl and mtllib. o, g, s and l are accepted and ignored.
+// Every material used in the file becomes one TriangleMesh.
+//
+// MTL statements mapped onto the renderer's materials:
+// Ke    -> any non-zero Ke makes the material emissive, the strongest channel times the emission scale is the
+//          strength and the colour becomes Ke's hue
+// illum -> 3, 5 and 8 are specular, 4, 6, 7 and 9 are refractive, anything else is diffuse
+// Kd    -> colour of diffuse materials, scaled from 0-1 to the renderer's 0-12 colour range
+// Ni    -> refractive_index of refractive materials, glass (1.5) if not given
+
+/// Colour of faces without a material, matching the grey walls of the demo scene
+static DEFAULT_COLOUR: DiffuseColour = DiffuseColour {r: 6.0, g: 6.0, b: 6.0};
+
+/// # Errors produced while importing an OBJ or MTL file
+#[derive(Debug)]
+pub enum ObjError {
+    /// The file could not be read
+    Io { path: String, source: std::io::Error },
+    /// A statement could not be understood, line numbers are 1-based
+    Malformed { path: String, line: usize, message: String },
+    /// The faces using a material do not make a usable mesh, such as positions scaled past the range of f64
+    Mesh { path: String, material: String, source: MeshError },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "{path}: {source}"),
+            ObjError::Malformed { path, line, message } => write!(f, "{path}:{line}: {message}"),
+            ObjError::Mesh { path, material, source } => write!(f, "{path}: faces using material '{material}': {source}"),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Mesh { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// # Placement of an imported model in the scene
+/// Positions are scaled uniformly, then translated. Emission from Ke is multiplied by emission_scale
+pub struct ObjOptions {
+    pub scale: f64,
+    pub translate: Vec3D,
+    pub emission_scale: f64,
+}
+
+impl Default for ObjOptions {
+    fn default() -> Self {
+        ObjOptions {
+            scale: 1.0,
+            translate: Vec3D {x: 0.0, y: 0.0, z: 0.0},
+            emission_scale: 1.0,
+        }
+    }
+}
+
+/// The statements of one newmtl block that matter to the renderer, turned into a Material once the block ends
+struct MtlRecord {
+    name: String,
+    diffuse: DiffuseColour,
+    emission: [f64; 3],
+    refractive_index: Option<f64>,
+    illum: u32,
+}
+
+impl MtlRecord {
+    fn material(&self, emission_scale: f64) -> Material {
+        let [r, g, b] = self.emission;
+        let strength = r.max(g).max(b);
+        if strength > 0.0 {
+            return Material::Emissive {
+                colour: DiffuseColour {r: r/strength*COLOUR_SCALE, g: g/strength*COLOUR_SCALE, b: b/strength*COLOUR_SCALE},
+                strength: strength*emission_scale,
+            };
+        }
+        match self.illum {
+            3 | 5 | 8 => Material::Specular,
+            4 | 6 | 7 | 9 => Material::Refractive { refractive_index: self.refractive_index.filter(|index| *index > 0.0).unwrap_or(1.5) },
+            _ => Material::Diffuse { colour: self.diffuse },
+        }
+    }
+}
+
+/// Faces collected for one material, with vertices de-indexed so positions, normals and uvs share one index
+#[derive(Default)]
+struct MeshBuilder {
+    positions: Vec<Vec3D>,
+    normals: Vec<Vec3D>,
+    uvs: Vec<[f64; 2]>,
+    indices: Vec<[usize; 3]>,
+    /// (position, uv, normal) reference triple -> vertex index
+    vertex_lookup: HashMap<(usize, Option<usize>, Option<usize>), usize>,
+    /// false as soon as one vertex comes without a normal / uv, the mesh then falls back to geometric normals / no uvs
+    all_normals: bool,
+    all_uvs: bool,
+}
+
+/// Line-oriented reader state shared by the OBJ and MTL parsers
+struct LineContext<'a> {
+    path: &'a str,
+    line: usize,
+}
+
+impl LineContext<'_> {
+    fn error(&self, message: String) -> ObjError {
+        ObjError::Malformed { path: self.path.to_string(), line: self.line, message }
+    }
+
+    /// Parse exactly `N` finite floats from the arguments of a statement,
+    /// extra trailing values (such as the optional w of v) are ignored
+    fn floats<const N: usize>(&self, keyword: &str, arguments: &[&str], required: usize) -> Result<[f64; N], ObjError> {
+        if arguments.len() < required {
+            return Err(self.error(format!("{keyword} needs {required} numbers, found {}", arguments.len())));
+        }
+        let mut values = [0.0_f64; N];
+        for (value, argument) in values.iter_mut().zip(arguments) {
+            *value = argument.parse().map_err(|_| self.error(format!("{keyword}: '{argument}' is not a number")))?;
+            if !value.is_finite() {
+                return Err(self.error(format!("{keyword}: '{argument}' is not a finite number")));
+            }
+        }
+        Ok(values)
+    }
+
+    /// Resolve a 1-based (or negative, relative) OBJ index against the number of elements defined so far
+    fn resolve_index(&self, reference: &str, count: usize, kind: &str) -> Result<usize, ObjError> {
+        let index: i64 = reference.parse().map_err(|_| self.error(format!("'{reference}' is not a valid {kind} index")))?;
+        let resolved = if index > 0 { index - 1 } else { count as i64 + index };
+        if index == 0 || resolved < 0 || resolved >= count as i64 {
+            return Err(self.error(format!("{kind} index {index} is out of range, {count} defined so far")));
+        }
+        Ok(resolved as usize)
+    }
+}
+
+/// Parse the text of an MTL file into materials by name. `path` is only used to label errors
+pub fn parse_mtl(source: &str, path: &str, emission_scale: f64) -> Result<HashMap<String, Material>, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<MtlRecord> = None;
+    let mut context = LineContext { path, line: 0 };
+
+    for (number, line) in source.lines().enumerate() {
+        context.line = number + 1;
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            None => continue,
+            Some(keyword) => keyword,
+        };
+        let arguments: Vec<&str> = tokens.collect();
+        if keyword == "newmtl" {
+            if let Some(record) = current.take() {
+                materials.insert(record.name.clone(), record.material(emission_scale));
+            }
+            if arguments.is_empty() {
+                return Err(context.error("newmtl needs a material name".to_string()));
+            }
+            current = Some(MtlRecord {
+                name: arguments.join(" "),
+                diffuse: DEFAULT_COLOUR,
+                emission: [0.0; 3],
+                refractive_index: None,
+                illum: 2,
+            });
+            continue;
+        }
+        let record = match current.as_mut() {
+            Some(current) => current,
+            None => return Err(context.error(format!("{keyword} before the first newmtl"))),
+        };
+        match keyword {
+            "Kd" => {
+                let [r, g, b] = context.floats::<3>(keyword, &arguments, 3)?;
+                record.diffuse = DiffuseColour {r: r*COLOUR_SCALE, g: g*COLOUR_SCALE, b: b*COLOUR_SCALE};
+            }
+            "Ke" => {
+                record.emission = context.floats::<3>(keyword, &arguments, 3)?;
+            }
+            "Ni" => {
+                let [refractive_index] = context.floats::<1>(keyword, &arguments, 1)?;
+                record.refractive_index = Some(refractive_index);
+            }
+            "illum" => {
+                record.illum = arguments.first()
+                    .and_then(|argument| argument.parse().ok())
+                    .ok_or_else(|| context.error("illum needs an illumination model number".to_string()))?;
+            }
+            // statements without an equivalent in the renderer
+            "Ka" | "Ks" | "Ns" | "d" | "Tr" | "Tf" | "Pr" | "Pm" | "Ps" | "Pc" | "Pcr" | "aniso" | "anisor" | "sharpness" => {}
+            _ if keyword.starts_with("map_") || keyword == "bump" || keyword == "disp" || keyword == "decal" || keyword == "refl" || keyword == "norm" => {}
+            _ => return Err(context.error(format!("unknown MTL statement '{keyword}'"))),
+        }
+    }
+    if let Some(record) = current {
+        materials.insert(record.name.clone(), record.material(emission_scale));
+    }
+    Ok(materials)
+}
+
+/// Parse the text of an OBJ file into one mesh per material.
+/// `directory` is where mtllib files are looked up, `path` is only used to label errors
+pub fn parse_obj(source: &str, path: &str, directory: &Path, options: &ObjOptions) -> Result<Vec<TriangleMesh>, ObjError> {
+    let mut fingerprint = FNV_OFFSET;
+    import_obj(source, path, directory, options, &mut fingerprint)
+}
+
+/// parse_obj, also hashing the text of every MTL file read into `fingerprint`
+fn import_obj(source: &str, path: &str, directory: &Path, options: &ObjOptions, fingerprint: &mut u64) -> Result<Vec<TriangleMesh>, ObjError> {
+    let mut positions: Vec<Vec3D> = Vec::new();
+    let mut normals: Vec<Vec3D> = Vec::new();
+    let mut uvs: Vec<[f64; 2]> = Vec::new();
+    let mut materials: HashMap<String, Material> = HashMap::new();
+    // meshes in order of first use, keyed by material name ("" for faces without a material)
+    let mut builders: Vec<(String, MeshBuilder)> = Vec::new();
+    let mut current_material = String::new();
+    let mut context = LineContext { path, line: 0 };
+
+    for (number, line) in source.lines().enumerate() {
+        context.line = number + 1;
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            None => continue,
+            Some(keyword) => keyword,
+        };
+        let arguments: Vec<&str> = tokens.collect();
+        match keyword {
+            "v" => {
+                let [x, y, z] = context.floats::<3>(keyword, &arguments, 3)?;
+                positions.push(Vec3D {x, y, z}.scalar_mult(options.scale).add(&options.translate));
+            }
+            "vn" => {
+                let [x, y, z] = context.floats::<3>(keyword, &arguments, 3)?;
+                let mut normal = Vec3D {x, y, z};
+                if normal.length() == 0.0 {
+                    return Err(context.error("vn: normal has zero length".to_string()));
+                }
+                normal.norm();
+                normals.push(normal);
+            }
+            "vt" => {
+                let [u, v] = context.floats::<2>(keyword, &arguments, 1)?;
+                uvs.push([u, v]);
+            }
+            "f" => {
+                if arguments.len() < 3 {
+                    return Err(context.error(format!("a face needs at least 3 vertices, found {}", arguments.len())));
+                }
+                let builder = match builders.iter().position(|(name, _)| *name == current_material) {
+                    Some(index) => &mut builders[index].1,
+                    None => {
+                        builders.push((current_material.clone(), MeshBuilder { all_normals: true, all_uvs: true, ..MeshBuilder::default() }));
+                        &mut builders.last_mut().unwrap().1
+                    }
+                };
+                let mut face = Vec::with_capacity(arguments.len());
+                for reference in &arguments {
+                    let mut parts = reference.split('/');
+                    let position = context.resolve_index(parts.next().unwrap_or(""), positions.len(), "vertex")?;
+                    let uv = match parts.next() {
+                        None | Some("") => None,
+                        Some(uv) => Some(context.resolve_index(uv, uvs.len(), "texture coordinate")?),
+                    };
+                    let normal = match parts.next() {
+                        None | Some("") => None,
+                        Some(normal) => Some(context.resolve_index(normal, normals.len(), "normal")?),
+                    };
+                    if parts.next().is_some() {
+                        return Err(context.error(format!("'{reference}' is not a valid face vertex")));
+                    }
+                    let key = (position, uv, normal);
+                    let vertex = match builder.vertex_lookup.get(&key) {
+                        Some(vertex) => *vertex,
+                        None => {
+                            let vertex = builder.positions.len();
+                            builder.positions.push(positions[position]);
+                            builder.normals.push(normal.map_or(Vec3D {x: 0.0, y: 0.0, z: 0.0}, |normal| normals[normal]));
+                            builder.uvs.push(uv.map_or([0.0, 0.0], |uv| uvs[uv]));
+                            builder.all_normals &= normal.is_some();
+                            builder.all_uvs &= uv.is_some();
+                            builder.vertex_lookup.insert(key, vertex);
+                            vertex
+                        }
+                    };
+                    face.push(vertex);
+                }
+                // triangulate the polygon as a fan around its first vertex
+                for i in 1..face.len() - 1 {
+                    builder.indices.push([face[0], face[i], face[i + 1]]);
+                }
+            }
+            "usemtl" => {
+                let name = arguments.join(" ");
+                if !materials.contains_key(&name) {
+                    return Err(context.error(format!("material '{name}' is not defined by any mtllib")));
+                }
+                current_material = name;
+            }
+            "mtllib" => {
+                for library in &arguments {
+                    let library_path = directory.join(library);
+                    let library_name = library_path.display().to_string();
+                    let library_source = fs::read_to_string(&library_path)
+                        .map_err(|source| ObjError::Io { path: library_name.clone(), source })?;
+                    *fingerprint = fnv1a(library_source.as_bytes(), *fingerprint);
+                    materials.extend(parse_mtl(&library_source, &library_name, options.emission_scale)?);
+                }
+            }
+            "o" | "g" | "s" | "l" => {}
+            _ => return Err(context.error(format!("unknown OBJ statement '{keyword}'"))),
+        }
+    }
+
+    builders.into_iter().map(|(name, builder)| {
+        let material = materials.get(&name).copied().unwrap_or(Material::Diffuse { colour: DEFAULT_COLOUR });
+        let build = || {
+            let mut mesh = TriangleMesh::new(builder.positions, builder.indices, material)?;
+            if builder.all_normals {
+                mesh = mesh.with_normals(builder.normals)?;
+            }
+            if builder.all_uvs {
+                mesh = mesh.with_uvs(builder.uvs)?;
+            }
+            Ok(mesh)
+        };
+        build().map_err(|source| ObjError::Mesh { path: path.to_string(), material: name.clone(), source })
+    }).collect()
+}
+
+/// Read an OBJ file (and the MTL files it references, relative to it) from disk
+pub fn load_obj(path: &Path, options: &ObjOptions) -> Result<Vec<TriangleMesh>, ObjError> {
+    Ok(load_obj_fingerprinted(path, options)?.0)
+}
+
+/// load_obj, also returning a hash of the OBJ file and every MTL file it read, so checkpoints notice edited models
+pub(crate) fn load_obj_fingerprinted(path: &Path, options: &ObjOptions) -> Result<(Vec<TriangleMesh>, u64), ObjError> {
+    let path_name = path.display().to_string();
+    let source = fs::read_to_string(path).map_err(|source| ObjError::Io { path: path_name.clone(), source })?;
+    let mut fingerprint = fnv1a(source.as_bytes(), FNV_OFFSET);
+    let meshes = import_obj(&source, &path_name, path.parent().unwrap_or(Path::new("")), options, &mut fingerprint)?;
+    Ok((meshes, fingerprint))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::type_structs::{Ray, SceneObject};
+
+    #[test]
+    fn parse_mtl_test() {
+        let source = "
+newmtl red
+Kd 0.5 0.0 0.0
+illum 2
+
+newmtl glass
+Kd 1.0 1.0 1.0
+Ni 1.45
+illum 7
+
+newmtl lamp
+Kd 0.8 0.8 0.8
+Ke 20 10 0
+";
+        let materials = parse_mtl(source, "test.mtl", 1.0).unwrap();
+        match materials["red"] {
+            Material::Diffuse { colour } => {
+                assert_eq!(colour.r, 6.0);
+                assert_eq!(colour.g, 0.0);
+            }
+            _ => panic!("expected a diffuse material"),
+        }
+        match materials["glass"] {
+            Material::Refractive { refractive_index } => assert_eq!(refractive_index, 1.45),
+            _ => panic!("expected a refractive material"),
+        }
+        match materials["lamp"] {
+            Material::Emissive { colour, strength } => {
+                assert_eq!(strength, 20.0);
+                assert_eq!(colour.r, 12.0);
+                assert_eq!(colour.g, 6.0);
+            }
+            _ => panic!("expected an emissive material"),
+        }
+    }
+
+    #[test]
+    fn parse_obj_test() {
+        // a quad written as one polygon with normals, plus a triangle using negative indices
+        let source = "
+o quad
+v 0 0 -1
+v 1 0 -1
+v 1 1 -1
+v 0 1 -1
+vn 0 0 1
+f 1//1 2//1 3//1 4//1
+v 5 5 5
+v 6 5 5
+v 5 6 5
+f -3 -2 -1
+";
+        let options = ObjOptions { scale: 2.0, ..ObjOptions::default() };
+        let meshes = parse_obj(source, "test.obj", Path::new(""), &options).unwrap();
+        assert_eq!(meshes.len(), 1);
+        // one triangle from the fan plus the triangle, the second face has no normals so they are dropped
+        assert_eq!(meshes[0].indices().len(), 3);
+        assert!(meshes[0].normals().is_empty());
+        let ray = Ray { origin: Vec3D {x: 1.0, y: 1.0, z: 0.0}, direction: Vec3D {x: 0.0, y: 0.0, z: -1.0} };
+        assert_eq!(meshes[0].intersect(&ray), 2.0);
+    }
+
+    #[test]
+    fn parse_obj_error_test() {
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n";
+        match parse_obj(source, "broken.obj", Path::new(""), &ObjOptions::default()) {
+            Err(ObjError::Malformed { path, line, .. }) => {
+                assert_eq!(path, "broken.obj");
+                assert_eq!(line, 4);
+            }
+            _ => panic!("expected a malformed line error"),
+        }
+        match parse_obj("v 0 zero 0\n", "broken.obj", Path::new(""), &ObjOptions::default()) {
+            Err(ObjError::Malformed { line, .. }) => assert_eq!(line, 1),
+            _ => panic!("expected a malformed line error"),
+        }
+        // non-finite values and normals without a direction are refused where they are defined
+        for (source, bad_line) in [("v 0 0 0\nv nan 0 0\n", 2), ("v 0 0 0\nvt 0 inf\n", 2), ("v 0 0 0\nvn 0 1 0\nvn 0 0 0\n", 3)] {
+            match parse_obj(source, "broken.obj", Path::new(""), &ObjOptions::default()) {
+                Err(ObjError::Malformed { line, .. }) => assert_eq!(line, bad_line),
+                _ => panic!("expected a malformed line error for {source:?}"),
+            }
+        }
+    }
+}
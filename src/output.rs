@@ -0,0 +1,158 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use image::{ImageBuffer, ImageError, Luma, Rgb, Rgb32FImage};
+
+use crate::film::{Film, Image};
+use crate::rendering_equation::RenderSettings;
+use crate::tonemapping::display_value;
+use crate::type_structs::DiffuseColour;
+
+// Image output
+//
+// The format is picked from the extension of the output path:
+// .exr -> OpenEXR with 32-bit float channels, written by the image crate
+// .pfm -> portable float map, little-endian 32-bit floats
+// anything else -> 8-bit image in whatever format the image crate associates with the extension, after the exposure,
+//                  tone mapping and sRGB encoding of the render settings
+// The float formats keep the linear radiance exactly as rendered, for tonemapping and compositing elsewhere.
+// Sample maps follow the same rule: float formats store the number of samples of every pixel, 8-bit images scale it
+// so that the maximum number of samples per pixel is white.
+
+/// # Errors produced while writing the rendered image
+#[derive(Debug)]
+pub enum OutputError {
+    /// The image crate could not encode or write the image
+    Image { path: String, source: ImageError },
+    /// The file could not be written
+    Io { path: String, source: std::io::Error },
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputError::Image { path, source } => write!(f, "{path}: {source}"),
+            OutputError::Io { path, source } => write!(f, "{path}: {source}"),
+        }
+    }
+}
+
+impl std::error::Error for OutputError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OutputError::Image { source, .. } => Some(source),
+            OutputError::Io { source, .. } => Some(source),
+        }
+    }
+}
+
+fn extension(path: &str) -> Option<String> {
+    Path::new(path).extension().map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+}
+
+/// Write the framebuffer to `path`, in the format given by its extension
+pub fn save_image(image: &Image, settings: &RenderSettings, path: &str) -> Result<(), OutputError> {
+    match extension(path).as_deref() {
+        Some("exr") => save_exr(image, path),
+        Some("pfm") => save_pfm(image, path),
+        _ => save_ldr(image, settings, path),
+    }
+}
+
+fn save_exr(image: &Image, path: &str) -> Result<(), OutputError> {
+    let buffer: Rgb32FImage = ImageBuffer::from_fn(image.width() as u32, image.height() as u32, |column, row| {
+        let pixel = image.get(column as i32, row as i32);
+        Rgb([pixel.r as f32, pixel.g as f32, pixel.b as f32])
+    });
+    buffer.save(path).map_err(|source| OutputError::Image { path: path.to_string(), source })
+}
+
+/// Encode the framebuffer as a colour PFM. The negative scale marks the data as little-endian,
+/// and rows are stored from the bottom of the image to the top
+fn encode_pfm(image: &Image) -> Vec<u8> {
+    let mut data = format!("PF\n{} {}\n-1.0\n", image.width(), image.height()).into_bytes();
+    data.reserve((image.width() * image.height() * 12) as usize);
+    for row in (0..image.height()).rev() {
+        for column in 0..image.width() {
+            let pixel = image.get(column, row);
+            for channel in [pixel.r, pixel.g, pixel.b] {
+                data.extend_from_slice(&(channel as f32).to_le_bytes());
+            }
+        }
+    }
+    data
+}
+
+fn save_pfm(image: &Image, path: &str) -> Result<(), OutputError> {
+    fs::write(path, encode_pfm(image)).map_err(|source| OutputError::Io { path: path.to_string(), source })
+}
+
+fn save_ldr(image: &Image, settings: &RenderSettings, path: &str) -> Result<(), OutputError> {
+    let buffer: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_fn(image.width() as u32, image.height() as u32, |column, row| {
+        let pixel = image.get(column as i32, row as i32);
+        Rgb([pixel.r, pixel.g, pixel.b].map(|channel| display_value(channel, settings.exposure, settings.tone_mapping)))
+    });
+    buffer.save(path).map_err(|source| OutputError::Image { path: path.to_string(), source })
+}
+
+/// Write the number of samples taken in every pixel to `path`, in the format given by its extension
+pub fn save_sample_map(sample_counts: &Film<u32>, settings: &RenderSettings, path: &str) -> Result<(), OutputError> {
+    let counts = sample_counts.map(|count| {
+        let count = *count as f64;
+        DiffuseColour {r: count, g: count, b: count}
+    });
+    match extension(path).as_deref() {
+        Some("exr") => save_exr(&counts, path),
+        Some("pfm") => save_pfm(&counts, path),
+        _ => {
+            let buffer: ImageBuffer<Luma<u8>, Vec<u8>> = ImageBuffer::from_fn(counts.width() as u32, counts.height() as u32, |column, row| {
+                let count = counts.get(column as i32, row as i32).r;
+                Luma([(count/settings.samples.max(1) as f64*255.0).round().min(255.0) as u8])
+            });
+            buffer.save(path).map_err(|source| OutputError::Image { path: path.to_string(), source })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A small framebuffer with a distinct value in every pixel
+    fn test_image(width: i32, height: i32) -> Image {
+        let mut image = Film::new(width, height, DiffuseColour {r: 0.0, g: 0.0, b: 0.0});
+        for row in 0..height {
+            for column in 0..width {
+                *image.get_mut(column, row) = DiffuseColour {r: column as f64 + 0.5, g: row as f64*1000.0, b: -0.25};
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn pfm_encoding_test() {
+        let data = encode_pfm(&test_image(3, 2));
+        let header = b"PF\n3 2\n-1.0\n";
+        assert_eq!(&data[..header.len()], header);
+        assert_eq!(data.len(), header.len() + 3*2*3*4);
+        // the first pixel stored is the bottom left one
+        let first: Vec<f32> = data[header.len()..header.len() + 12].chunks(4)
+            .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect();
+        assert_eq!(first, vec![0.5, 1000.0, -0.25]);
+    }
+
+    #[test]
+    fn exr_round_trip_test() {
+        let settings = RenderSettings::default();
+        let path = std::env::temp_dir().join(format!("rusty_render_test_{}.exr", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        save_image(&test_image(5, 3), &settings, &path).unwrap();
+        let loaded = image::open(&path).unwrap().into_rgb32f();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.dimensions(), (5, 3));
+        // values far outside the 8-bit range survive unchanged
+        assert_eq!(loaded.get_pixel(4, 2).0, [4.5, 2000.0, -0.25]);
+    }
+}
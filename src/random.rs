@@ -0,0 +1,80 @@
+use rand::Rng;
+
+// Random numbers for rendering
+//
+// Every sample of every pixel gets its own PCG32 generator, seeded from the render seed and the sample number and
+// using the pixel as its stream. A sample therefore sees the same random numbers no matter which thread traces it,
+// how the image is split into tiles, or whether it is taken in one go or in a later progressive pass.
+
+/// Multiplier of the 64-bit linear congruential step underlying PCG32
+static PCG_MULTIPLIER: u64 = 6_364_136_223_846_793_005;
+
+/// # PCG32 random number generator
+/// O'Neill's PCG-XSH-RR with 64 bits of state, and one of 2^63 streams chosen by the increment
+#[derive(Clone, Debug)]
+pub(crate) struct Pcg32 {
+    state: u64,
+    increment: u64,
+}
+
+impl Pcg32 {
+    /// A generator on stream `stream`, started from `seed` the way the reference implementation does
+    pub(crate) fn new(seed: u64, stream: u64) -> Pcg32 {
+        let mut rng = Pcg32 {
+            state: 0,
+            increment: (stream << 1) | 1,
+        };
+        rng.step();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.step();
+        rng
+    }
+
+    /// The generator for one sample of one pixel of a render
+    pub(crate) fn for_sample(seed: u64, pixel: u64, sample: u32) -> Pcg32 {
+        Pcg32::new(splitmix64(seed ^ splitmix64(sample as u64)), pixel)
+    }
+
+    fn step(&mut self) {
+        self.state = self.state.wrapping_mul(PCG_MULTIPLIER).wrapping_add(self.increment);
+    }
+}
+
+impl Rng for Pcg32 {
+    fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.step();
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+}
+
+/// The SplitMix64 finaliser, spreads nearby inputs such as consecutive sample numbers over all 64 bits
+pub(crate) fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pcg32_reference_test() {
+        // first outputs of the reference pcg32-demo, seeded with 42 on stream 54
+        let mut rng = Pcg32::new(42, 54);
+        let outputs: Vec<u32> = (0..6).map(|_| rng.next_u32()).collect();
+        assert_eq!(outputs, vec![0xa15c02b7, 0x7b47f409, 0xba1d3330, 0x83d2f293, 0xbfa4784b, 0xcbed606e]);
+    }
+
+    #[test]
+    fn for_sample_test() {
+        let first = |mut rng: Pcg32| rng.next_u64();
+        assert_eq!(first(Pcg32::for_sample(1, 2, 3)), first(Pcg32::for_sample(1, 2, 3)));
+        assert_ne!(first(Pcg32::for_sample(1, 2, 3)), first(Pcg32::for_sample(1, 2, 4)));
+        assert_ne!(first(Pcg32::for_sample(1, 2, 3)), first(Pcg32::for_sample(1, 3, 3)));
+        assert_ne!(first(Pcg32::for_sample(1, 2, 3)), first(Pcg32::for_sample(2, 2, 3)));
+    }
+}
@@ -1,28 +1,183 @@
 use std::f64::consts::PI;
+use std::fmt;
+use std::ops::Range;
+use std::time::Instant;
 
-use rand::Rng;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
 
-use crate::type_structs::{DiffuseColour, Ray, RenderScene, Vec3D};
+use crate::camera::CameraFrame;
+use crate::error::Error;
+use crate::film::{pixel_count, Film, Image};
+use crate::filter::Filter;
+use crate::material::Material;
+use crate::sampler::{HaltonSampler, IndependentSampler, Sampler, SamplerKind, SobolSampler, StratifiedSampler};
+use crate::tonemapping::ToneMapping;
+use crate::type_structs::{DiffuseColour, Intersection, Ray, RenderScene, Vec3D};
 
-pub(crate) const WIDTH: i32 = 800;
-pub(crate) const HEIGHT: i32 = 800;
-static FIELD_OF_VIEW_HORIZONTAL: f64 = PI/4.0;
-static FIELD_OF_VIEW_VERTICAL: f64 = (HEIGHT/WIDTH) as f64 * PI/4.0;
+/// Side length in pixels of the square tiles the image is split into for parallel rendering
+static TILE_SIZE: i32 = 32;
+/// Depth after which paths are terminated by Russian roulette
+static ROULETTE_DEPTH: i32 = 5;
+/// Probability that a path continues at each Russian roulette test, survivors are weighted by its inverse
+static ROULETTE_SURVIVAL: f64 = 0.9;
+/// Samples every pixel takes before adaptive sampling may judge it converged
+static ADAPTIVE_MIN_SAMPLES: u32 = 16;
+/// Luminance below which adaptive sampling bounds the absolute rather than the relative error
+static ADAPTIVE_FLOOR: f64 = 0.01;
+/// Sum of filter weights below which a pixel is left black. Filters with negative lobes can leave sparsely sampled
+/// pixels with a sum near zero or below it, and dividing by that would flip or blow up the radiance
+static MIN_FILTER_WEIGHT: f64 = 1e-3;
 
+/// # Heuristic weighting BSDF sampling against light sampling
+/// Both strategies can find the same light, multiple importance sampling weights each by how likely it was to
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum MisHeuristic {
+    /// Weights proportional to the densities
+    Balance,
+    /// Weights proportional to the squared densities, favouring the better strategy more strongly
+    Power,
+}
 
-/// Get the coordinates on the camera plane
-fn cam_plane_coordinate(x: i32,y: i32) -> Vec3D {
-    Vec3D {
-        x: (((2*x - WIDTH) as f64)/WIDTH as f64)*FIELD_OF_VIEW_HORIZONTAL.tan(),
-        y: -((2*y-HEIGHT)as f64/HEIGHT as f64)*FIELD_OF_VIEW_VERTICAL.tan(),
-        z: -1.0,
+/// # Settings controlling a single render
+/// Resolution, samples per pixel, maximum path depth, the output image path,
+/// an optional seed for reproducible renders, an optional number of worker threads (all cores if None), whether the
+/// scene should be intersected through its BVH (built by the render if the scene has none) or by testing every
+/// object, the heuristic used for combining
+/// BSDF and light sampling, and the exposure (in stops) and tone mapping applied when writing 8-bit images.
+/// Progressive renders take one sample per pixel over the whole image at a time and can save snapshots every
+/// `snapshot_passes` passes or `snapshot_seconds` seconds, and keep a checkpoint at `checkpoint` to resume from.
+/// `sampler` picks how the random numbers of a pixel's samples are generated. With an `adaptive_threshold` a pixel
+/// stops sampling once the relative standard error of its luminance drops below it, `samples` then being the most it
+/// takes, and `sample_map` is an optional image of the number of samples taken per pixel. Samples are splatted into
+/// the image through the reconstruction `filter`, over `filter_radius` pixels or the filter's default radius
+pub struct RenderSettings {
+    pub width: i32,
+    pub height: i32,
+    pub samples: i32,
+    pub max_depth: i32,
+    pub output: String,
+    pub seed: Option<u64>,
+    pub threads: Option<usize>,
+    pub use_bvh: bool,
+    pub mis_heuristic: MisHeuristic,
+    pub exposure: f64,
+    pub tone_mapping: ToneMapping,
+    pub progressive: bool,
+    pub snapshot_passes: Option<i32>,
+    pub snapshot_seconds: Option<f64>,
+    pub checkpoint: Option<String>,
+    pub sampler: SamplerKind,
+    pub adaptive_threshold: Option<f64>,
+    pub sample_map: Option<String>,
+    pub filter: Filter,
+    pub filter_radius: Option<f64>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            width: 800,
+            height: 800,
+            samples: 8,
+            max_depth: 64,
+            output: String::from("Converged.png"),
+            seed: None,
+            threads: None,
+            use_bvh: true,
+            mis_heuristic: MisHeuristic::Power,
+            exposure: 0.0,
+            tone_mapping: ToneMapping::Aces,
+            progressive: false,
+            snapshot_passes: None,
+            snapshot_seconds: None,
+            checkpoint: None,
+            sampler: SamplerKind::Sobol,
+            adaptive_threshold: None,
+            sample_map: None,
+            filter: Filter::Box,
+            filter_radius: None,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Radius of the reconstruction filter in pixels, the filter's own default unless one is set
+    pub fn reconstruction_radius(&self) -> f64 {
+        self.filter_radius.unwrap_or_else(|| self.filter.default_radius())
+    }
+
+    /// Check the settings a render depends on, the first value that cannot be used is returned as an error
+    pub fn check(&self) -> Result<(), RenderError> {
+        let invalid = |field, message: &str| Err(RenderError::InvalidSetting { field, message: message.to_string() });
+        if self.width < 1 {
+            return invalid("width", "must be at least 1");
+        }
+        if self.height < 1 {
+            return invalid("height", "must be at least 1");
+        }
+        if pixel_count(self.width, self.height).is_none() {
+            return invalid("height", &format!("{}x{} pixels is more than the {} an image can have", self.width, self.height, i32::MAX));
+        }
+        if self.samples < 0 {
+            return invalid("samples", "must not be negative");
+        }
+        if self.max_depth < 1 {
+            return invalid("max_depth", "must be at least 1");
+        }
+        // written so that NaN is refused as well
+        if self.filter_radius.is_some_and(|radius| !(radius > 0.0 && radius.is_finite())) {
+            return invalid("filter_radius", "must be a finite number greater than zero");
+        }
+        if self.adaptive_threshold.is_some_and(|threshold| !(threshold > 0.0 && threshold.is_finite())) {
+            return invalid("adaptive_threshold", "must be a finite number greater than zero");
+        }
+        if self.snapshot_passes.is_some_and(|passes| passes < 1) {
+            return invalid("snapshot_passes", "must be at least 1");
+        }
+        if self.snapshot_seconds.is_some_and(|seconds| seconds.is_nan() || seconds < 0.0) {
+            return invalid("snapshot_seconds", "must not be negative");
+        }
+        Ok(())
+    }
+}
+
+/// # Errors that stop a render from starting
+#[derive(Debug)]
+pub enum RenderError {
+    /// The worker thread pool could not be created
+    ThreadPool(ThreadPoolBuildError),
+    /// The camera has no valid orientation (eye equals target, or up is parallel to the viewing direction)
+    DegenerateCamera,
+    /// A render setting holds a value the renderer cannot use
+    InvalidSetting { field: &'static str, message: String },
+    /// The accumulation to carry on was made for an image of a different size
+    AccumulationSize { width: i32, height: i32 },
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::ThreadPool(error) => write!(f, "could not start the render threads: {error}"),
+            RenderError::DegenerateCamera => write!(f, "the camera eye, target and up vector do not define a view"),
+            RenderError::InvalidSetting { field, message } => write!(f, "{field}: {message}"),
+            RenderError::AccumulationSize { width, height } => write!(f, "the accumulated samples are for a {width}x{height} image"),
+        }
     }
 }
 
-/// Get a hemisphere sample vector
-fn hemisphere() -> Vec3D {
-    let rand: f64 = rand::thread_rng().gen_range(0.0, 1.0);
-    // let rand2: f64 = rand::thread_rng().gen_range(0.0, 1.0);
+impl std::error::Error for RenderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RenderError::ThreadPool(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Get a cosine-weighted hemisphere sample vector around +z
+pub(crate) fn hemisphere<S: Sampler>(sampler: &mut S) -> Vec3D {
+    let (rand, rand2) = sampler.get_2d();
     // let radius = (1.0-rand*rand);
     // let phi = 2.0*PI*rand2;
     // return Vec3D {
@@ -32,29 +187,88 @@ fn hemisphere() -> Vec3D {
     // };
 
     let radius = rand.sqrt();
-    let angle = 2.0*PI*rand::thread_rng().gen_range(0.0, 1.0);
+    let angle = 2.0*PI*rand2;
     let x_pos = radius*angle.cos();
     let y_pos = radius*angle.sin();
-    return Vec3D {
+    Vec3D {
         x: x_pos,
         y: y_pos,
         z: f64::max(0.0,1.0-rand).sqrt()
     }
 }
 
-fn trace(ray: &mut Ray, render_scene: &RenderScene, recursion_depth: i32, colour: &mut DiffuseColour) {
+/// MIS weight of a strategy that chose a direction with density `pdf` against another that would have chosen it with `other_pdf`
+fn mis_weight(heuristic: MisHeuristic, pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = match heuristic {
+        MisHeuristic::Balance => (pdf, other_pdf),
+        MisHeuristic::Power => (pdf*pdf, other_pdf*other_pdf),
+    };
+    if a + b > 0.0 { a/(a + b) } else { 0.0 }
+}
+
+/// Closest intersection of the ray with the scene, through the scene's BVH unless settings.use_bvh is off
+fn closest_hit<'a>(render_scene: &'a RenderScene, settings: &RenderSettings, ray: &Ray) -> Option<Intersection<'a>> {
+    if settings.use_bvh {
+        render_scene.intersect(ray)
+    }else {
+        render_scene.intersect_brute_force(ray)
+    }
+}
+
+/// Radiance arriving at `hit_point` directly from one randomly chosen light, reflected back along `direction`.
+/// Lights are picked uniformly, so the estimate is scaled by their number. The contribution is MIS weighted against
+/// the material finding the same light by sampling its BSDF
+fn sample_direct_light<S: Sampler>(hit_point: &Vec3D, direction: &Vec3D, normal: &Vec3D, material: &Material, render_scene: &RenderScene, settings: &RenderSettings, sampler: &mut S) -> DiffuseColour {
+    let mut direct = DiffuseColour {r: 0.0, g: 0.0, b: 0.0};
+    if render_scene.lights.is_empty() {
+        return direct;
+    }
+    let choice = (sampler.get_1d()*render_scene.lights.len() as f64) as usize;
+    let light_index = render_scene.lights[choice.min(render_scene.lights.len() - 1)];
+    let light = render_scene.objects_list[light_index].as_ref();
+    let light_sample = match light.sample_light(hit_point, sampler.get_2d()) {
+        None => {return direct;}
+        Some(x) => x
+    };
+    let mut bsdf = material.eval(direction, &light_sample.direction, normal);
+    let cosine = light_sample.direction.dot(normal).abs();
+    let light_pdf = light_sample.pdf/render_scene.lights.len() as f64;
+    if light_pdf <= 0.0 || bsdf.r + bsdf.g + bsdf.b == 0.0 {
+        return direct;
+    }
+
+    // shadow test, the light is visible if it is the first thing the ray towards it hits
+    let shadow_ray = Ray {origin: *hit_point, direction: light_sample.direction};
+    match closest_hit(render_scene, settings, &shadow_ray) {
+        Some(blocker) if blocker.index == light_index => {
+            let weight = mis_weight(settings.mis_heuristic, light_pdf, material.pdf(direction, &light_sample.direction, normal));
+            direct = bsdf.mult_colour_return(light.material().emitted()).mult_return(cosine/light_pdf*weight);
+        }
+        _ => {}
+    }
+    direct
+}
+
+/// Follow a path from the given ray and add the light carried back along it to colour.
+/// `bsdf_pdf` is the density with which the previous bounce sampled the ray's direction, None for camera rays and
+/// specular bounces. Emission from the scene's lights is then MIS weighted against the direct light sampling done
+/// at the previous bounce
+fn trace<S: Sampler>(ray: &mut Ray, render_scene: &RenderScene, settings: &RenderSettings, recursion_depth: i32, bsdf_pdf: Option<f64>, colour: &mut DiffuseColour, sampler: &mut S) {
     let mut roulette_factor = 1.0;
-    // exit condition
-    if recursion_depth >= 5 {
-        if rand::thread_rng().gen_range(-1.0, 1.0) <= 0.1 {
+    // exit conditions
+    if recursion_depth >= settings.max_depth {
+        return;
+    }
+    if recursion_depth >= ROULETTE_DEPTH {
+        if sampler.get_1d() >= ROULETTE_SURVIVAL {
             return;
         }else {
-            // weight of subsequent results
-            roulette_factor = 1.0/(0.9);
+            // weight of subsequent results, keeping the estimate unbiased
+            roulette_factor = 1.0/ROULETTE_SURVIVAL;
         }
     }
 
-    let intersection = render_scene.intersect(ray);
+    let intersection = closest_hit(render_scene, settings, ray);
     // if the intersection is none, return, else extract it
     let intersection_validated = match intersection {
         None => {return;}
@@ -63,119 +277,770 @@ fn trace(ray: &mut Ray, render_scene: &RenderScene, recursion_depth: i32, colour
         }
     };
 
-    // todo remove
-    // colour.add(intersection_validated.object.colour().mult_return(21.25));
-    // return;
-
     let hit_point = ray.origin.add(&ray.direction.scalar_mult(intersection_validated.distance));
-    let mut normal = intersection_validated.object.normal(&hit_point);
+    let normal = intersection_validated.object.surface_normal(ray, intersection_validated.distance);
+    let material = intersection_validated.object.material();
 
-    ray.origin = hit_point.clone();
     // at this point we have detected the nearest object and can access its properties
-    let mut emission_factor = DiffuseColour {
-        r: intersection_validated.object.colour().r /12.0*intersection_validated.object.emission(),
-        g: intersection_validated.object.colour().g /12.0*intersection_validated.object.emission(),
-        b: intersection_validated.object.colour().b /12.0*intersection_validated.object.emission(),
-    };
+    let mut emission_factor = material.emitted();
+    if let Some(bsdf_pdf) = bsdf_pdf {
+        if render_scene.lights.contains(&intersection_validated.index) {
+            let light_pdf = intersection_validated.object.light_pdf(&ray.origin, &ray.direction)/render_scene.lights.len() as f64;
+            emission_factor.mult(mis_weight(settings.mis_heuristic, bsdf_pdf, light_pdf));
+        }
+    }
     emission_factor.mult(roulette_factor);
     colour.add(emission_factor);
+    ray.origin = hit_point;
 
+    // continue the path in a direction chosen by the material, light sources end it
+    let sample = match material.sample(&ray.direction, &normal, sampler) {
+        None => {return;}
+        Some(sample) => sample
+    };
 
+    // next-event estimation, specular materials can only be lit through the sampled direction
+    if !sample.specular {
+        let mut direct = sample_direct_light(&hit_point, &ray.direction, &normal, material, render_scene, settings, sampler);
+        direct.mult(roulette_factor);
+        colour.add(direct);
+    }
+    if sample.weight.r + sample.weight.g + sample.weight.b == 0.0 {
+        return;
+    }
 
-    if intersection_validated.object.material_type() == 1 { // diffuse material
-        let mut rotation_x = Vec3D {x: 0.0, y: 0.0, z: 0.0};
-        let mut rotation_y = Vec3D {x: 0.0, y: 0.0, z: 0.0};
-        normal.orthonormal_system(&mut rotation_x,&mut rotation_y);
+    ray.direction = sample.direction;
 
-        let sample_direction = hemisphere();
+    let mut temp_colour = DiffuseColour {r: 0.0,g: 0.0, b: 0.0};
 
-        let rotated_direction = Vec3D {
-            x: Vec3D {x: rotation_x.x, y: rotation_y.x, z: normal.x}.dot(&sample_direction),
-            y: Vec3D {x: rotation_x.y, y: rotation_y.y, z: normal.y}.dot(&sample_direction),
-            z: Vec3D {x: rotation_x.z, y: rotation_y.z, z: normal.z}.dot(&sample_direction),
-        };
+    trace(ray, render_scene, settings, recursion_depth+1, (!sample.specular).then_some(sample.pdf), &mut temp_colour, sampler);
 
-        ray.direction = rotated_direction.clone();
+    colour.add(temp_colour.mult_colour_return(sample.weight).mult_return(roulette_factor));
+}
 
-        let cosine_direction = ray.direction.dot(&normal);
+/// # Statistics of the samples taken in one pixel
+/// Their number, mean luminance and sum of squared luminance deviations, updated with Welford's algorithm so the
+/// variance of the pixel can be estimated while sampling
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PixelSamples {
+    pub(crate) count: u32,
+    pub(crate) mean: f64,
+    pub(crate) m2: f64,
+}
 
-        let mut temp_colour = DiffuseColour {r: 0.0,g: 0.0, b: 0.0};
+impl PixelSamples {
+    /// No samples yet
+    pub(crate) fn new() -> PixelSamples {
+        PixelSamples {
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
 
-        trace(ray, render_scene, recursion_depth+1, &mut temp_colour);
+    /// Add one radiance sample
+    pub(crate) fn add(&mut self, colour: &DiffuseColour) {
+        let luminance = luminance(colour);
+        self.count += 1;
+        let delta = luminance - self.mean;
+        self.mean += delta/self.count as f64;
+        self.m2 += delta*(luminance - self.mean);
+    }
 
-        colour.add(temp_colour.mult_colour_return(intersection_validated.object.colour()).mult_return(cosine_direction*0.1*roulette_factor));
-        // println!("r:{},g:{},b:{}", colour.r, colour.g,colour.b)
-    }else if intersection_validated.object.material_type() == 2 { // specular material
-        let cosine_direction = ray.direction.dot(&normal);
-        let subtracted = ray.direction.subtract(&normal.scalar_mult(cosine_direction*2.0));
-        ray.set_direction(&subtracted);
-        ray.direction.norm();
-        let mut temp_colour_2 = DiffuseColour {r: 0.0,g: 0.0, b: 0.0};
+    /// Add the samples of a later batch, combining the statistics with Chan et al.'s parallel update
+    pub(crate) fn merge(&mut self, other: &PixelSamples) {
+        if other.count == 0 {
+            return;
+        }
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        self.mean += delta*other.count as f64/count as f64;
+        self.m2 += other.m2 + delta*delta*self.count as f64*other.count as f64/count as f64;
+        self.count = count;
+    }
 
-        trace(ray, render_scene, recursion_depth+1, &mut temp_colour_2);
+    /// Whether the standard error of the mean luminance is below `threshold` relative to the mean. Dark pixels are
+    /// judged against ADAPTIVE_FLOOR instead, so they do not take every sample chasing a relative error of noise
+    pub(crate) fn converged(&self, threshold: f64) -> bool {
+        if self.count < ADAPTIVE_MIN_SAMPLES {
+            return false;
+        }
+        let count = self.count as f64;
+        let standard_error = (self.m2/(count - 1.0)/count).max(0.0).sqrt();
+        standard_error <= threshold*self.mean.max(ADAPTIVE_FLOOR)
+    }
+}
 
-        colour.add(temp_colour_2.mult_return(roulette_factor));
-        // println!("r:{},g:{},b:{}", colour.r, colour.g,colour.b)
+/// Rec. 709 luminance of a radiance value
+fn luminance(colour: &DiffuseColour) -> f64 {
+    0.2126*colour.r + 0.7152*colour.g + 0.0722*colour.b
+}
 
-    }else if intersection_validated.object.material_type() == 3 { // refractive material
-        let mut r_index = intersection_validated.object.refractive_index();
-        let ratio = ((1.0-r_index)/(1.0+r_index)).powi(2);
+/// # Filtered radiance of one pixel
+/// The sum of the radiance of every sample splatted into the pixel times its filter weight, and the sum of the weights
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct FilteredPixel {
+    pub(crate) weighted_sum: DiffuseColour,
+    pub(crate) weight: f64,
+}
 
-        // if inside the medium
-        if normal.dot(&ray.direction) > 0.0 {
-            normal = normal.scalar_mult(-1.0);
-            r_index = 1.0/r_index;
+impl FilteredPixel {
+    pub(crate) fn new() -> FilteredPixel {
+        FilteredPixel {
+            weighted_sum: DiffuseColour {r:0.0,g:0.0,b:0.0},
+            weight: 0.0,
         }
-        r_index = 1.0/r_index;
+    }
 
-        let cosine_direction_1 = -1.0 * normal.dot(&ray.direction);
-        let cosine_direction_2 = 1.0 - (r_index*r_index*(1.0-(cosine_direction_1*cosine_direction_1)));
-        // Schlick approximation
-        let fresnel_probability_factor = ratio + (1.0-ratio)*((1.0-cosine_direction_1).powi(5));
+    fn merge(&mut self, other: &FilteredPixel) {
+        self.weighted_sum.add(other.weighted_sum);
+        self.weight += other.weight;
+    }
 
-        if cosine_direction_2 > 0.0 && rand::thread_rng().gen_range(0.0, 1.0) > fresnel_probability_factor {
-            ray.set_direction(&ray.direction.scalar_mult(r_index).add(&normal.scalar_mult(r_index*cosine_direction_1-cosine_direction_2.sqrt())));
-            ray.direction.norm();
-        }else {
-            ray.set_direction(&ray.direction.add(&normal.scalar_mult(cosine_direction_1*2.0)));
-            ray.direction.norm();
+    /// The reconstructed radiance, black where no sample has landed or the filter weights (nearly) cancel out
+    pub(crate) fn colour(&self) -> DiffuseColour {
+        let mut colour = self.weighted_sum;
+        colour.mult(if self.weight > MIN_FILTER_WEIGHT { 1.0/self.weight } else { 0.0 });
+        colour
+    }
+}
+
+/// # Filtered radiance splatted by the samples of one tile
+/// Covers the tile grown by the filter radius on every side, as samples near its edge also reach pixels of the
+/// neighbouring tiles. Stored row by row
+struct SplatBuffer {
+    column_start: i32,
+    column_end: i32,
+    row_start: i32,
+    row_end: i32,
+    pixels: Vec<FilteredPixel>,
+}
+
+impl SplatBuffer {
+    fn new(tile: &Tile, settings: &RenderSettings) -> SplatBuffer {
+        let reach = settings.reconstruction_radius().ceil() as i32;
+        let column_start = i32::max(tile.column_start - reach, 0);
+        let column_end = i32::min(tile.column_end + reach, settings.width);
+        let row_start = i32::max(tile.row_start - reach, 0);
+        let row_end = i32::min(tile.row_end + reach, settings.height);
+        SplatBuffer {
+            column_start,
+            column_end,
+            row_start,
+            row_end,
+            pixels: vec![FilteredPixel::new(); ((column_end - column_start) * (row_end - row_start)) as usize],
         }
+    }
 
-        let mut temp_colour_3 = DiffuseColour {r: 0.0,g: 0.0, b: 0.0};
+    fn index(&self, column: i32, row: i32) -> usize {
+        ((row - self.row_start) * (self.column_end - self.column_start) + column - self.column_start) as usize
+    }
 
-        trace(ray, render_scene, recursion_depth+1, &mut temp_colour_3);
+    /// Add a sample at film position (x, y), in pixels from the top left corner of the image, to every pixel whose
+    /// centre is within the filter radius
+    fn splat(&mut self, x: f64, y: f64, colour: &DiffuseColour, settings: &RenderSettings) {
+        let radius = settings.reconstruction_radius();
+        // pixels whose centre c + 0.5 satisfies -radius <= x - (c + 0.5) < radius
+        let columns = (x - radius - 0.5).floor() as i32 + 1..(x + radius - 0.5).floor() as i32 + 1;
+        let rows = (y - radius - 0.5).floor() as i32 + 1..(y + radius - 0.5).floor() as i32 + 1;
+        for row in i32::max(rows.start, self.row_start)..i32::min(rows.end, self.row_end) {
+            for column in i32::max(columns.start, self.column_start)..i32::min(columns.end, self.column_end) {
+                let weight = settings.filter.evaluate(x - (column as f64 + 0.5), y - (row as f64 + 0.5), radius);
+                if weight != 0.0 {
+                    let index = self.index(column, row);
+                    let mut weighted = *colour;
+                    weighted.mult(weight);
+                    self.pixels[index].weighted_sum.add(weighted);
+                    self.pixels[index].weight += weight;
+                }
+            }
+        }
+    }
+}
 
-        colour.add(temp_colour_3.mult_return(1.15*roulette_factor));
-        // println!("r:{},g:{},b:{}", colour.r, colour.g,colour.b)
+/// Normalized device coordinates of a film position in pixels from the top left corner,
+/// x from -1 (left) to 1 (right) and y from 1 (top) to -1 (bottom)
+fn film_to_device(x: f64, y: f64, settings: &RenderSettings) -> (f64, f64) {
+    (
+        2.0*x/settings.width as f64 - 1.0,
+        1.0 - 2.0*y/settings.height as f64,
+    )
+}
 
+/// Trace the samples numbered `samples` for one pixel, splat them into `splats` and return their statistics. With
+/// adaptive sampling the pixel stops early once it has converged. Each sample draws from its own sampler derived
+/// from the render's seed, so its value only depends on the seed and its number
+fn simulate_per_pixel(column: i32, row: i32, render_scene: &RenderScene, settings: &RenderSettings, context: &RenderContext, samples: Range<u32>, splats: &mut SplatBuffer) -> PixelSamples {
+    match settings.sampler {
+        SamplerKind::Independent => simulate_with::<IndependentSampler>(column, row, render_scene, settings, context, samples, splats),
+        SamplerKind::Stratified => simulate_with::<StratifiedSampler>(column, row, render_scene, settings, context, samples, splats),
+        SamplerKind::Halton => simulate_with::<HaltonSampler>(column, row, render_scene, settings, context, samples, splats),
+        SamplerKind::Sobol => simulate_with::<SobolSampler>(column, row, render_scene, settings, context, samples, splats),
     }
 }
 
-pub(crate) fn simulate_per_pixel(column: i32, row: i32, render_scene: &RenderScene, samples: i32, image_pixels: &mut Vec<DiffuseColour>) {
-    for _ in 0..samples {
+fn simulate_with<S: Sampler>(column: i32, row: i32, render_scene: &RenderScene, settings: &RenderSettings, context: &RenderContext, samples: Range<u32>, splats: &mut SplatBuffer) -> PixelSamples {
+    let mut pixel = PixelSamples::new();
+    for sample in samples {
+        let pixel_number = (row * settings.width + column) as u64;
+        let mut sampler = S::for_sample(context.seed, pixel_number, sample, settings.samples as u32);
         let mut colour_master = DiffuseColour {r:0.0,g:0.0,b:0.0};
 
-        let mut camera = cam_plane_coordinate(column, row);
+        // a random point in the pixel, the reconstruction filter takes care of anti-aliasing
+        let (offset_x, offset_y) = sampler.get_2d();
+        let (film_x, film_y) = (column as f64 + offset_x, row as f64 + offset_y);
+        let (x, y) = film_to_device(film_x, film_y, settings);
+
+        let lens_sample = sampler.get_2d();
+        let mut ray = context.camera.generate_ray(x, y, lens_sample);
+        trace(&mut ray, render_scene, settings, 0, None, &mut colour_master, &mut sampler);
+
+        pixel.add(&colour_master);
+        splats.splat(film_x, film_y, &colour_master, settings);
+        if settings.adaptive_threshold.is_some_and(|threshold| pixel.converged(threshold)) {
+            break;
+        }
+    }
+    pixel
+}
+
+/// # A rectangular block of pixels rendered as one unit of work
+struct Tile {
+    column_start: i32,
+    column_end: i32,
+    row_start: i32,
+    row_end: i32,
+}
+
+/// Split the image into tiles of at most TILE_SIZE x TILE_SIZE pixels
+fn build_tiles(settings: &RenderSettings) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for row_start in (0..settings.height).step_by(TILE_SIZE as usize) {
+        for column_start in (0..settings.width).step_by(TILE_SIZE as usize) {
+            tiles.push(Tile {
+                column_start,
+                column_end: i32::min(column_start + TILE_SIZE, settings.width),
+                row_start,
+                row_end: i32::min(row_start + TILE_SIZE, settings.height),
+            });
+        }
+    }
+    tiles
+}
+
+/// # Camera frame, worker pool, tiles and seed shared by every batch of a render
+struct RenderContext {
+    camera: CameraFrame,
+    pool: ThreadPool,
+    tiles: Vec<Tile>,
+    seed: u64,
+}
+
+/// Render the samples numbered `samples` for every pixel that has not converged yet and add them to
+/// `accumulation`, with tiles distributed across the pool. Random numbers depend only on the seed, the pixel and the
+/// sample, and tiles are added in a fixed order, so a seeded render does not depend on the number of threads or on
+/// which thread picked up which tile
+fn render_batch(render_scene: &RenderScene, settings: &RenderSettings, context: &RenderContext, samples: Range<u32>, accumulation: &mut Accumulation) {
+    let previous = &accumulation.pixels;
+    let rendered_tiles: Vec<(&Tile, Vec<PixelSamples>, SplatBuffer)> = context.pool.install(|| {
+        context.tiles.par_iter().map(|tile| {
+            let mut tile_pixels = Vec::with_capacity(((tile.column_end - tile.column_start) * (tile.row_end - tile.row_start)) as usize);
+            let mut splats = SplatBuffer::new(tile, settings);
+            for column in tile.column_start..tile.column_end {
+                for row in tile.row_start..tile.row_end {
+                    let converged = settings.adaptive_threshold.is_some_and(|threshold| {
+                        previous.get(column, row).converged(threshold)
+                    });
+                    tile_pixels.push(if converged {
+                        PixelSamples::new()
+                    }else {
+                        simulate_per_pixel(column, row, render_scene, settings, context, samples.clone(), &mut splats)
+                    });
+                }
+            }
+            (tile, tile_pixels, splats)
+        }).collect()
+    });
+
+    // add the tiles to the framebuffer
+    for (tile, tile_pixels, splats) in rendered_tiles {
+        let mut tile_pixels = tile_pixels.into_iter();
+        for column in tile.column_start..tile.column_end {
+            for row in tile.row_start..tile.row_end {
+                if let Some(pixel) = tile_pixels.next() {
+                    accumulation.pixels.get_mut(column, row).merge(&pixel);
+                }
+            }
+        }
+        for row in splats.row_start..splats.row_end {
+            for column in splats.column_start..splats.column_end {
+                accumulation.filtered.get_mut(column, row).merge(&splats.pixels[splats.index(column, row)]);
+            }
+        }
+    }
+}
+
+fn prepare_render(render_scene: &RenderScene, settings: &RenderSettings, seed: u64) -> Result<RenderContext, RenderError> {
+    settings.check()?;
+    let camera = render_scene.camera.frame(settings.width, settings.height).ok_or(RenderError::DegenerateCamera)?;
+    if settings.use_bvh {
+        render_scene.ensure_bvh();
+    }
+    let pool = ThreadPoolBuilder::new().num_threads(settings.threads.unwrap_or(0)).build().map_err(RenderError::ThreadPool)?;
+    Ok(RenderContext {
+        camera,
+        pool,
+        tiles: build_tiles(settings),
+        seed,
+    })
+}
+
+/// Render the scene as set out in `settings` and return the image of linear radiance.
+/// Without a seed in the settings a random one is used
+pub fn render(render_scene: &RenderScene, settings: &RenderSettings) -> Result<Image, Error> {
+    Ok(render_image(render_scene, settings)?.image())
+}
+
+/// Render the whole image with all samples of a pixel taken at once,
+/// with tiles distributed across settings.threads worker threads (all cores if None).
+/// Without a seed in the settings a random one is used
+pub fn render_image(render_scene: &RenderScene, settings: &RenderSettings) -> Result<Accumulation, RenderError> {
+    // checked before the accumulation is sized from the settings
+    settings.check()?;
+    let mut accumulation = Accumulation::new(settings, settings.seed.unwrap_or_else(rand::random));
+    let context = prepare_render(render_scene, settings, accumulation.seed)?;
+    render_batch(render_scene, settings, &context, 0..settings.samples as u32, &mut accumulation);
+    accumulation.passes = settings.samples;
+    Ok(accumulation)
+}
+
+/// # Radiance accumulated by a render
+/// Statistics of the samples taken in every pixel, the filtered radiance splatted into every pixel, the number of
+/// passes taken so far by a progressive render and the seed every sample derives its random numbers from.
+/// Continuing with the same seed gives the image an uninterrupted render would have produced
+pub struct Accumulation {
+    pub(crate) pixels: Film<PixelSamples>,
+    pub(crate) filtered: Film<FilteredPixel>,
+    pub passes: i32,
+    pub seed: u64,
+}
 
-        // randomized anti-aliasing
-        camera.x = camera.x + rand::thread_rng().gen_range(-1.0, 1.0)/700.0;
-        camera.y = camera.y + rand::thread_rng().gen_range(-1.0, 1.0)/700.0;
-        let origin = Vec3D {x:0.0,y:0.0,z:0.0};
-        camera = camera.subtract(&origin);
-        camera.norm();
+impl Accumulation {
+    /// An empty accumulation for an image of the size in `settings`
+    pub fn new(settings: &RenderSettings, seed: u64) -> Accumulation {
+        Accumulation {
+            pixels: Film::new(settings.width, settings.height, PixelSamples::new()),
+            filtered: Film::new(settings.width, settings.height, FilteredPixel::new()),
+            passes: 0,
+            seed,
+        }
+    }
+
+    /// The image so far, reconstructed from the filtered samples
+    pub fn image(&self) -> Image {
+        self.filtered.map(FilteredPixel::colour)
+    }
+
+    /// The number of samples taken in every pixel
+    pub fn sample_counts(&self) -> Film<u32> {
+        self.pixels.map(|pixel| pixel.count)
+    }
+}
+
+/// Render the image one sample per pixel at a time, adding passes to `accumulation` until it holds settings.samples,
+/// or with adaptive sampling until every pixel has converged. A fresh accumulation starts a new render, one loaded
+/// from a checkpoint carries on where it stopped.
+/// `snapshot` is called with the accumulation every settings.snapshot_passes passes or settings.snapshot_seconds
+/// seconds, whichever comes first; it is not called after the final pass
+pub fn render_progressive<F: FnMut(&Accumulation)>(render_scene: &RenderScene, settings: &RenderSettings, accumulation: &mut Accumulation, mut snapshot: F) -> Result<(), RenderError> {
+    let context = prepare_render(render_scene, settings, accumulation.seed)?;
+    let (width, height) = (accumulation.pixels.width(), accumulation.pixels.height());
+    if (width, height) != (settings.width, settings.height) {
+        return Err(RenderError::AccumulationSize { width, height });
+    }
+
+    let mut last_snapshot = Instant::now();
+    while accumulation.passes < settings.samples {
+        let pass = accumulation.passes as u32;
+        render_batch(render_scene, settings, &context, pass..pass + 1, accumulation);
+        accumulation.passes += 1;
+
+        let all_converged = settings.adaptive_threshold.is_some_and(|threshold| {
+            accumulation.pixels.pixels().iter().all(|pixel| pixel.converged(threshold))
+        });
+        if accumulation.passes == settings.samples || all_converged {
+            break;
+        }
+        let passes_due = settings.snapshot_passes.is_some_and(|every| accumulation.passes % every == 0);
+        let time_due = settings.snapshot_seconds.is_some_and(|seconds| last_snapshot.elapsed().as_secs_f64() >= seconds);
+        if passes_due || time_due {
+            snapshot(accumulation);
+            last_snapshot = Instant::now();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Pcg32;
+    use crate::type_structs::{Disk, Plane, Sphere};
 
-        let mut ray = Ray {
-            origin,
-            direction: camera.clone()
+    /// Mean and variance of the radiance seen looking straight down at a floor lit by a sphere light overhead
+    fn floor_radiance(render_scene: &RenderScene, heuristic: MisHeuristic, samples: usize) -> (f64, f64) {
+        // depth 2 keeps only the direct lighting of the floor
+        let settings = RenderSettings { max_depth: 2, mis_heuristic: heuristic, ..RenderSettings::default() };
+        let mut sampler = IndependentSampler::new(Pcg32::new(7, 0));
+        let mut total = 0.0;
+        let mut total_squared = 0.0;
+        for _ in 0..samples {
+            let mut ray = Ray {origin: Vec3D {x: 0.0, y: 0.5, z: 0.0}, direction: Vec3D {x: 0.0, y: -1.0, z: 0.0}};
+            let mut colour = DiffuseColour {r: 0.0, g: 0.0, b: 0.0};
+            trace(&mut ray, render_scene, &settings, 0, None, &mut colour, &mut sampler);
+            total += colour.r;
+            total_squared += colour.r*colour.r;
+        }
+        let mean = total/samples as f64;
+        (mean, total_squared/samples as f64 - mean*mean)
+    }
+
+    fn floor_scene(floor: Material, light_radius: f64) -> RenderScene {
+        RenderScene::new(vec![
+            Box::new(Plane {
+                normal: Vec3D {x: 0.0, y: 1.0, z: 0.0},
+                distance_to_origin: 0.0,
+                material: floor,
+            }),
+            Box::new(Sphere {
+                center: Vec3D {x: 0.0, y: 4.0, z: 0.0},
+                radius: light_radius,
+                material: Material::Emissive { colour: DiffuseColour {r: 12.0, g: 12.0, b: 12.0}, strength: 1.0 },
+            }),
+        ]).unwrap()
+    }
+
+    #[test]
+    fn direct_light_test() {
+        let mut render_scene = floor_scene(Material::Diffuse { colour: DiffuseColour {r: 6.0, g: 6.0, b: 6.0} }, 1.0);
+        assert_eq!(render_scene.lights, vec![1]);
+        // albedo 0.5 under a light of radiance 1 covering a cone of half angle asin(1/4)
+        let expected = 0.5/16.0;
+        for heuristic in [MisHeuristic::Balance, MisHeuristic::Power] {
+            let (with_light_sampling, _) = floor_radiance(&render_scene, heuristic, 20000);
+            assert!((with_light_sampling - expected).abs() < 0.02*expected, "{with_light_sampling} != {expected}");
+        }
+
+        // without light sampling the estimate is noisier but converges to the same value
+        render_scene.lights.clear();
+        let (bsdf_only, _) = floor_radiance(&render_scene, MisHeuristic::Power, 200000);
+        assert!((bsdf_only - expected).abs() < 0.05*expected, "{bsdf_only} != {expected}");
+    }
+
+    #[test]
+    fn disk_light_test() {
+        let mut render_scene = floor_scene(Material::Diffuse { colour: DiffuseColour {r: 6.0, g: 6.0, b: 6.0} }, 1.0);
+        render_scene.objects_list[1] = Box::new(Disk {
+            center: Vec3D {x: 0.0, y: 4.0, z: 0.0},
+            normal: Vec3D {x: 0.0, y: -1.0, z: 0.0},
+            radius: 1.0,
+            material: Material::Emissive { colour: DiffuseColour {r: 12.0, g: 12.0, b: 12.0}, strength: 1.0 },
+        });
+        render_scene.collect_lights();
+        assert_eq!(render_scene.lights, vec![1]);
+        // albedo 0.5 under a disk of radiance 1 and radius 1 at height 4, whose irradiance is pi r^2 / (h^2 + r^2)
+        let expected = 0.5/17.0;
+        let (with_light_sampling, _) = floor_radiance(&render_scene, MisHeuristic::Power, 20000);
+        assert!((with_light_sampling - expected).abs() < 0.02*expected, "{with_light_sampling} != {expected}");
+        render_scene.lights.clear();
+        let (bsdf_only, _) = floor_radiance(&render_scene, MisHeuristic::Power, 200000);
+        assert!((bsdf_only - expected).abs() < 0.05*expected, "{bsdf_only} != {expected}");
+    }
+
+    #[test]
+    fn russian_roulette_test() {
+        // inside a closed diffuse sphere of albedo 0.8 with a small light at its centre, most of the light reaching
+        // the wall has bounced more than ROULETTE_DEPTH times. By symmetry the wall radiance W is the same everywhere:
+        // the light covers s = (r/R)^2 of the cosine-weighted hemisphere, so W = albedo (Le s + W (1 - s))
+        let (albedo, radiance, s) = (0.8, 16.0, 1.0/64.0);
+        let render_scene = RenderScene::new(vec![
+            Box::new(Sphere {
+                center: Vec3D {x: 0.0, y: 0.0, z: 0.0},
+                radius: 2.0,
+                material: Material::Diffuse { colour: DiffuseColour {r: 9.6, g: 9.6, b: 9.6} },
+            }),
+            Box::new(Sphere {
+                center: Vec3D {x: 0.0, y: 0.0, z: 0.0},
+                radius: 0.25,
+                material: Material::Emissive { colour: DiffuseColour {r: 12.0, g: 12.0, b: 12.0}, strength: radiance },
+            }),
+        ]).unwrap();
+        let expected = albedo*radiance*s/(1.0 - albedo*(1.0 - s));
+        let settings = RenderSettings { max_depth: 100, ..RenderSettings::default() };
+        let mut sampler = IndependentSampler::new(Pcg32::new(3, 0));
+        let samples = 40000;
+        let mut total = 0.0;
+        for _ in 0..samples {
+            let mut ray = Ray {origin: Vec3D {x: 0.0, y: 1.0, z: 0.0}, direction: Vec3D {x: 0.0, y: 1.0, z: 0.0}};
+            let mut colour = DiffuseColour {r: 0.0, g: 0.0, b: 0.0};
+            trace(&mut ray, &render_scene, &settings, 0, None, &mut colour, &mut sampler);
+            total += colour.r;
+        }
+        let mean = total/samples as f64;
+        assert!((mean - expected).abs() < 0.03*expected, "{mean} != {expected}");
+    }
+
+    #[test]
+    fn multiple_importance_sampling_test() {
+        // a glossy floor reflecting a small light, which BSDF sampling rarely finds
+        let mut render_scene = floor_scene(Material::Glossy { colour: DiffuseColour {r: 12.0, g: 12.0, b: 12.0}, exponent: 20.0 }, 0.1);
+        let (balance, balance_variance) = floor_radiance(&render_scene, MisHeuristic::Balance, 20000);
+        let (power, power_variance) = floor_radiance(&render_scene, MisHeuristic::Power, 20000);
+        render_scene.lights.clear();
+        let (bsdf_only, bsdf_variance) = floor_radiance(&render_scene, MisHeuristic::Power, 400000);
+
+        assert!((balance - bsdf_only).abs() < 0.1*bsdf_only, "{balance} != {bsdf_only}");
+        assert!((power - bsdf_only).abs() < 0.1*bsdf_only, "{power} != {bsdf_only}");
+        assert!(balance_variance*10.0 < bsdf_variance && power_variance*10.0 < bsdf_variance);
+    }
+
+    #[test]
+    fn progressive_render_test() {
+        let render_scene = floor_scene(Material::Diffuse { colour: DiffuseColour {r: 6.0, g: 6.0, b: 6.0} }, 1.0);
+        let settings = RenderSettings {
+            width: 8,
+            height: 8,
+            samples: 5,
+            seed: Some(3),
+            progressive: true,
+            snapshot_passes: Some(2),
+            ..RenderSettings::default()
         };
-        trace(&mut ray, render_scene, 0, &mut colour_master);
+        let mut snapshots = Vec::new();
+        let mut accumulation = Accumulation::new(&settings, 3);
+        render_progressive(&render_scene, &settings, &mut accumulation, |so_far| snapshots.push((so_far.image(), so_far.passes))).unwrap();
+        let image_pixels = accumulation.image();
+        assert_eq!(snapshots.iter().map(|(_, passes)| *passes).collect::<Vec<_>>(), vec![2, 4]);
+        assert!(accumulation.sample_counts().pixels().iter().all(|count| *count == 5));
 
-        // if colour_master.r != 0.0 || colour_master.g != 0.0 || colour_master.b != 0.0 {
-        //     println!("r:{},g:{},b:{}", colour_master.r, colour_master.g, colour_master.b); // todo no colour out of trace
-        // }
+        // the final image is the average of all passes, so it extends the last snapshot by one pass
+        let (last, _) = &snapshots[1];
+        for (pixel, last) in image_pixels.pixels().iter().zip(last.pixels()) {
+            let final_pass = pixel.r*5.0 - last.r*4.0;
+            assert!(final_pass >= -1e-9);
+        }
+
+        // stopping after two passes and carrying on gives exactly the uninterrupted image
+        let mut resumed = Accumulation::new(&settings, 3);
+        let interrupted = RenderSettings { width: 8, height: 8, samples: 2, ..RenderSettings::default() };
+        render_progressive(&render_scene, &interrupted, &mut resumed, |_| {}).unwrap();
+        render_progressive(&render_scene, &settings, &mut resumed, |_| {}).unwrap();
+        assert_eq!(resumed.passes, 5);
+        for (pixel, resumed_pixel) in image_pixels.pixels().iter().zip(resumed.image().pixels()) {
+            assert_eq!(pixel.r, resumed_pixel.r);
+        }
+    }
+
+    #[test]
+    fn seeded_render_test() {
+        let mut render_scene = floor_scene(Material::Glossy { colour: DiffuseColour {r: 10.0, g: 10.0, b: 10.0}, exponent: 50.0 }, 1.0);
+        render_scene.camera.eye = Vec3D {x: 0.0, y: 2.0, z: 6.0};
+        render_scene.camera.target = Vec3D {x: 0.0, y: 1.0, z: 0.0};
+        let render = |threads: usize, use_bvh: bool| {
+            let settings = RenderSettings { width: 40, height: 40, samples: 3, seed: Some(11), threads: Some(threads), use_bvh, ..RenderSettings::default() };
+            render_image(&render_scene, &settings).unwrap().image()
+        };
+        // bit-identical whatever the number of threads, and whether or not the BVH the render builds is used
+        let single = render(1, true);
+        let pixels = |image: &Image| image.pixels().iter().map(|pixel| [pixel.r, pixel.g, pixel.b]).collect::<Vec<_>>();
+        assert_eq!(pixels(&single), pixels(&render(4, true)));
+        assert_eq!(pixels(&single), pixels(&render(2, false)));
+        assert!(single.pixels().iter().any(|pixel| pixel.r > 0.0));
 
-        // set the pixel
-        image_pixels[(row+column*WIDTH) as usize] = image_pixels[(row+column*WIDTH) as usize].add_return(colour_master.mult_return(1.0/samples as f64));
-        // println!("Set {column},{row} as r:{},g:{},b:{}", image_pixels[(row+column*WIDTH) as usize].r, image_pixels[(row+column*WIDTH) as usize].g,image_pixels[(row+column*WIDTH) as usize].b)
+        // a progressive render takes the same samples in the same order
+        let settings = RenderSettings { width: 40, height: 40, samples: 3, threads: Some(3), ..RenderSettings::default() };
+        let mut accumulation = Accumulation::new(&settings, 11);
+        render_progressive(&render_scene, &settings, &mut accumulation, |_| {}).unwrap();
+        assert_eq!(pixels(&single), pixels(&accumulation.image()));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn non_square_render_test() {
+        for (width, height) in [(1920, 1080), (300, 900)] {
+            // a light up and to the right of the view, halfway between the centre and the edges of the image.
+            // A narrow field of view keeps the perspective from stretching it off the axis
+            let half_height = 5.0*(PI/24.0).tan();
+            let half_width = half_height*width as f64/height as f64;
+            let mut render_scene = RenderScene::new(vec![
+                Box::new(Sphere {
+                    center: Vec3D {x: 0.5*half_width, y: 0.5*half_height, z: -5.0},
+                    radius: 0.1*half_height,
+                    material: Material::Emissive { colour: DiffuseColour {r: 12.0, g: 12.0, b: 12.0}, strength: 1.0 },
+                }),
+            ]).unwrap();
+            render_scene.camera.vertical_fov = PI/12.0;
+            let settings = RenderSettings { width, height, samples: 1, max_depth: 1, seed: Some(3), ..RenderSettings::default() };
+            let image = render_image(&render_scene, &settings).unwrap().image();
+            assert_eq!((image.width(), image.height()), (width, height));
+
+            let (mut left, mut right, mut top, mut bottom) = (width, 0, height, 0);
+            for row in 0..height {
+                for column in 0..width {
+                    if image.get(column, row).r > 0.0 {
+                        left = left.min(column);
+                        right = right.max(column);
+                        top = top.min(row);
+                        bottom = bottom.max(row);
+                    }
+                }
+            }
+            // centred around three quarters across and a quarter down
+            let (centre_x, centre_y) = ((left + right) as f64/2.0, (top + bottom) as f64/2.0);
+            assert!((centre_x/width as f64 - 0.75).abs() < 0.02, "{width}x{height}: {centre_x}");
+            assert!((centre_y/height as f64 - 0.25).abs() < 0.02, "{width}x{height}: {centre_y}");
+            // round, not stretched along either axis, and a tenth of the image height across
+            let (across, tall) = ((right - left + 1) as f64, (bottom - top + 1) as f64);
+            assert!((across/tall - 1.0).abs() < 0.05, "{width}x{height}: {across}x{tall}");
+            assert!((tall/height as f64 - 0.1).abs() < 0.01, "{width}x{height}: {tall}");
+        }
+    }
+
+    #[test]
+    fn invalid_settings_test() {
+        let render_scene = floor_scene(Material::Diffuse { colour: DiffuseColour {r: 6.0, g: 6.0, b: 6.0} }, 1.0);
+        let settings = RenderSettings { width: 0, ..RenderSettings::default() };
+        assert!(matches!(render_image(&render_scene, &settings), Err(RenderError::InvalidSetting { field: "width", .. })));
+        let settings = RenderSettings { samples: -1, ..RenderSettings::default() };
+        assert!(matches!(render(&render_scene, &settings), Err(Error::Render(RenderError::InvalidSetting { field: "samples", .. }))));
+        // NaN passes every comparison, so it must be refused explicitly
+        let refused = [
+            ("max_depth", RenderSettings { max_depth: 0, ..RenderSettings::default() }),
+            ("height", RenderSettings { width: 65536, height: 65536, ..RenderSettings::default() }),
+            ("filter_radius", RenderSettings { filter_radius: Some(f64::NAN), ..RenderSettings::default() }),
+            ("filter_radius", RenderSettings { filter_radius: Some(-1.0), ..RenderSettings::default() }),
+            ("adaptive_threshold", RenderSettings { adaptive_threshold: Some(f64::NAN), ..RenderSettings::default() }),
+            ("adaptive_threshold", RenderSettings { adaptive_threshold: Some(f64::INFINITY), ..RenderSettings::default() }),
+            ("snapshot_seconds", RenderSettings { snapshot_seconds: Some(-5.0), ..RenderSettings::default() }),
+            ("snapshot_seconds", RenderSettings { snapshot_seconds: Some(f64::NAN), ..RenderSettings::default() }),
+        ];
+        for (expected, settings) in refused {
+            assert!(matches!(settings.check(), Err(RenderError::InvalidSetting { field, .. }) if field == expected), "{expected}");
+        }
+        assert!(RenderSettings { snapshot_seconds: Some(0.0), max_depth: 1, ..RenderSettings::default() }.check().is_ok());
+
+        // carrying on an accumulation of another size would index past its pixels
+        let settings = RenderSettings { width: 8, height: 4, samples: 1, ..RenderSettings::default() };
+        let mut accumulation = Accumulation::new(&RenderSettings { width: 4, height: 8, ..RenderSettings::default() }, 1);
+        let result = render_progressive(&render_scene, &settings, &mut accumulation, |_| {});
+        assert!(matches!(result, Err(RenderError::AccumulationSize { width: 4, height: 8 })));
+    }
+
+    #[test]
+    fn pixel_samples_test() {
+        let values = [0.5, 2.0, 1.0, 4.0, 0.0, 3.5];
+        let mut all = PixelSamples::new();
+        let mut first = PixelSamples::new();
+        let mut second = PixelSamples::new();
+        for (i, value) in values.iter().enumerate() {
+            let colour = DiffuseColour {r: *value, g: *value, b: *value};
+            all.add(&colour);
+            if i < 2 { first.add(&colour) } else { second.add(&colour) }
+        }
+        // Welford's running statistics match the direct computation, also when merged from two batches
+        let mean = values.iter().sum::<f64>()/6.0;
+        let m2: f64 = values.iter().map(|value| (value - mean)*(value - mean)).sum();
+        first.merge(&second);
+        for pixel in [all, first] {
+            assert_eq!(pixel.count, 6);
+            assert!((pixel.mean - mean).abs() < 1e-12);
+            assert!((pixel.m2 - m2).abs() < 1e-12);
+        }
+        // too few samples to judge, whatever the threshold
+        assert!(!all.converged(1e9));
+    }
+
+    #[test]
+    fn adaptive_sampling_test() {
+        // a flat, directly lit floor converges long before the maximum number of samples
+        let mut render_scene = floor_scene(Material::Diffuse { colour: DiffuseColour {r: 6.0, g: 6.0, b: 6.0} }, 1.0);
+        render_scene.camera.eye = Vec3D {x: 0.0, y: 1.0, z: 0.0};
+        render_scene.camera.target = Vec3D {x: 0.0, y: 0.0, z: -0.01};
+        let settings = RenderSettings {
+            width: 8,
+            height: 8,
+            samples: 1000,
+            max_depth: 2,
+            seed: Some(5),
+            adaptive_threshold: Some(0.05),
+            ..RenderSettings::default()
+        };
+        let accumulation = render_image(&render_scene, &settings).unwrap();
+        let counts = accumulation.sample_counts();
+        assert!(counts.pixels().iter().all(|count| *count >= ADAPTIVE_MIN_SAMPLES && *count < 1000), "{counts:?}");
+        assert!(accumulation.pixels.pixels().iter().all(|pixel| pixel.converged(0.05)));
+
+        // progressive renders stop once every pixel has converged
+        let mut progressive = Accumulation::new(&settings, 5);
+        render_progressive(&render_scene, &settings, &mut progressive, |_| {}).unwrap();
+        assert!(progressive.passes < 1000);
+        assert!(progressive.pixels.pixels().iter().all(|pixel| pixel.converged(0.05)));
+    }
+
+    #[test]
+    fn splat_test() {
+        // a tent of radius 1 spreads a sample over the four nearest pixel centres
+        let settings = RenderSettings { width: 4, height: 4, filter: Filter::Tent, ..RenderSettings::default() };
+        let tiles = build_tiles(&settings);
+        let mut splats = SplatBuffer::new(&tiles[0], &settings);
+        splats.splat(1.75, 2.5, &DiffuseColour {r: 1.0, g: 1.0, b: 1.0}, &settings);
+        let weights: Vec<f64> = splats.pixels.iter().map(|pixel| pixel.weight).collect();
+        assert_eq!(weights.iter().filter(|weight| **weight > 0.0).count(), 2);
+        assert!((weights[splats.index(1, 2)] - 0.75).abs() < 1e-12);
+        assert!((weights[splats.index(2, 2)] - 0.25).abs() < 1e-12);
+
+        // a pixel reached only by the negative lobes of a Lanczos filter stays black instead of turning negative
+        let settings = RenderSettings { width: 4, height: 4, filter: Filter::Lanczos, ..RenderSettings::default() };
+        let mut splats = SplatBuffer::new(&tiles[0], &settings);
+        splats.splat(2.0, 0.5, &DiffuseColour {r: 1.0, g: 1.0, b: 1.0}, &settings);
+        splats.splat(0.5, 2.0, &DiffuseColour {r: 1.0, g: 1.0, b: 1.0}, &settings);
+        let corner = &splats.pixels[splats.index(0, 0)];
+        assert!(corner.weight < 0.0);
+        assert_eq!(corner.colour().r, 0.0);
+
+        // whatever the filter, a constant image is reconstructed exactly, up to the edges
+        let mut sampler = IndependentSampler::new(Pcg32::new(3, 0));
+        for filter in [Filter::Box, Filter::Tent, Filter::Gaussian, Filter::Mitchell, Filter::Lanczos] {
+            let settings = RenderSettings { width: 12, height: 12, filter, ..RenderSettings::default() };
+            let tile = Tile { column_start: 0, column_end: 12, row_start: 0, row_end: 12 };
+            let mut splats = SplatBuffer::new(&tile, &settings);
+            for _ in 0..20000 {
+                let (x, y) = sampler.get_2d();
+                splats.splat(x*12.0, y*12.0, &DiffuseColour {r: 0.5, g: 2.0, b: 0.0}, &settings);
+            }
+            for pixel in &splats.pixels {
+                let colour = pixel.colour();
+                assert!((colour.r - 0.5).abs() < 1e-9 && (colour.g - 2.0).abs() < 1e-9, "{filter:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn build_tiles_test() {
+        let settings = RenderSettings { width: 70, height: 45, ..RenderSettings::default() };
+        let tiles = build_tiles(&settings);
+        assert_eq!(tiles.len(), 3 * 2);
+
+        // every pixel is covered by exactly one tile
+        let mut covered = vec![0; (settings.width * settings.height) as usize];
+        for tile in &tiles {
+            assert!(tile.column_end - tile.column_start <= TILE_SIZE);
+            assert!(tile.row_end - tile.row_start <= TILE_SIZE);
+            for column in tile.column_start..tile.column_end {
+                for row in tile.row_start..tile.row_end {
+                    covered[(row * settings.width + column) as usize] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|count| *count == 1));
+    }
+}
@@ -0,0 +1,348 @@
+use rand::Rng;
+
+use crate::random::{splitmix64, Pcg32};
+
+// Sample generation
+//
+// A sampler hands out the numbers in [0, 1) that one sample of one pixel uses to pick its position in the pixel, its
+// point on the lens and every direction and light along its path. Each request takes the next dimension. The
+// independent sampler draws them at random; the others spread the samples of a pixel evenly over every dimension so
+// they cover it better than random numbers would, while random scrambling per pixel and dimension keeps the
+// estimates unbiased and the remaining error looking like noise rather than patterns.
+// Stratification is over `sample_count` samples; a progressive render resumed with more samples keeps converging,
+// but its extra samples are no longer stratified with the first ones.
+
+/// # Source of the numbers driving one sample of one pixel
+pub(crate) trait Sampler {
+    /// The sampler for sample number `sample` out of `sample_count` of a pixel, scrambled by the render seed
+    fn for_sample(seed: u64, pixel: u64, sample: u32, sample_count: u32) -> Self where Self: Sized;
+    /// The next dimension, in [0, 1)
+    fn get_1d(&mut self) -> f64;
+    /// The next two dimensions, stratified together where the sampler supports it
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+/// # Sampler selected for a render
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum SamplerKind {
+    /// Independent uniform random numbers
+    Independent,
+    /// Jittered strata, in shuffled order for every dimension
+    Stratified,
+    /// Halton sequence with a random offset per pixel
+    Halton,
+    /// Sobol sequence with hash-based Owen scrambling
+    Sobol,
+}
+
+/// Hash of the pixel, dimension and seed, for scrambling
+fn scramble_seed(seed: u64, pixel: u64, dimension: u32) -> u64 {
+    splitmix64(seed ^ splitmix64(pixel ^ splitmix64(dimension as u64)))
+}
+
+/// Convert 32 random bits to a float in [0, 1)
+fn to_unit(bits: u32) -> f64 {
+    bits as f64/4_294_967_296.0
+}
+
+/// # Independent uniform random numbers
+pub(crate) struct IndependentSampler {
+    rng: Pcg32,
+}
+
+impl IndependentSampler {
+    pub(crate) fn new(rng: Pcg32) -> IndependentSampler {
+        IndependentSampler { rng }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn for_sample(seed: u64, pixel: u64, sample: u32, _sample_count: u32) -> IndependentSampler {
+        IndependentSampler::new(Pcg32::for_sample(seed, pixel, sample))
+    }
+    fn get_1d(&mut self) -> f64 {
+        self.rng.next_f64()
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.rng.next_f64(), self.rng.next_f64())
+    }
+}
+
+/// Kensler's hash-based permutation of 0..length, from "Correlated Multi-Jittered Sampling"
+fn permute(mut index: u32, length: u32, seed: u32) -> u32 {
+    let mut mask = length - 1;
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+    // cycle walking, permute within the next power of two until the result falls inside the range
+    loop {
+        index ^= seed;
+        index = index.wrapping_mul(0xe170893d);
+        index ^= seed >> 16;
+        index ^= (index & mask) >> 4;
+        index ^= seed >> 8;
+        index = index.wrapping_mul(0x0929eb3f);
+        index ^= seed >> 23;
+        index ^= (index & mask) >> 1;
+        index = index.wrapping_mul(1 | seed >> 27);
+        index = index.wrapping_mul(0x6935fa69);
+        index ^= (index & mask) >> 11;
+        index = index.wrapping_mul(0x74dcb303);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0x9e501cc3);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0xc860a3df);
+        index &= mask;
+        index ^= index >> 5;
+        if index < length {
+            break;
+        }
+    }
+    (index.wrapping_add(seed)) % length
+}
+
+/// # Jittered stratified sampling
+/// Every dimension is split into `sample_count` strata, or a grid of about that many cells for pairs of dimensions,
+/// and each sample takes a random point in its own stratum. The strata are visited in a different shuffled order
+/// for every dimension, so dimensions are not correlated with each other
+pub(crate) struct StratifiedSampler {
+    rng: Pcg32,
+    seed: u64,
+    pixel: u64,
+    sample: u32,
+    sample_count: u32,
+    dimension: u32,
+}
+
+impl StratifiedSampler {
+    /// The stratum of this sample among `strata`, shuffled by the current dimension. Samples past the first
+    /// `strata` start another round with a fresh shuffle
+    fn stratum(&self, strata: u32) -> u32 {
+        let round = self.sample/strata;
+        let seed = scramble_seed(self.seed ^ round as u64, self.pixel, self.dimension) as u32;
+        permute(self.sample % strata, strata, seed)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn for_sample(seed: u64, pixel: u64, sample: u32, sample_count: u32) -> StratifiedSampler {
+        StratifiedSampler {
+            rng: Pcg32::for_sample(seed, pixel, sample),
+            seed,
+            pixel,
+            sample,
+            sample_count: sample_count.max(1),
+            dimension: 0,
+        }
+    }
+    fn get_1d(&mut self) -> f64 {
+        let stratum = self.stratum(self.sample_count);
+        self.dimension += 1;
+        (stratum as f64 + self.rng.next_f64())/self.sample_count as f64
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        // a grid of columns x rows cells with at least one cell per sample
+        let columns = (self.sample_count as f64).sqrt().floor() as u32;
+        let rows = self.sample_count.div_ceil(columns);
+        let cell = self.stratum(columns*rows);
+        self.dimension += 1;
+        (
+            ((cell % columns) as f64 + self.rng.next_f64())/columns as f64,
+            ((cell / columns) as f64 + self.rng.next_f64())/rows as f64,
+        )
+    }
+}
+
+/// Bases of the Halton dimensions, dimensions past these fall back to random numbers
+static PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+/// The digits of `index` in `base` mirrored around the radix point
+fn radical_inverse(mut index: u32, base: u32) -> f64 {
+    let inverse_base = 1.0/base as f64;
+    let mut factor = inverse_base;
+    let mut result = 0.0;
+    while index > 0 {
+        result += (index % base) as f64*factor;
+        index /= base;
+        factor *= inverse_base;
+    }
+    result
+}
+
+/// # Halton sequence
+/// Dimension d is the radical inverse of the sample number in the d-th prime base, shifted by a random offset per
+/// pixel and dimension (a Cranley-Patterson rotation) so neighbouring pixels do not share the same points
+pub(crate) struct HaltonSampler {
+    rng: Pcg32,
+    seed: u64,
+    pixel: u64,
+    sample: u32,
+    dimension: u32,
+}
+
+impl Sampler for HaltonSampler {
+    fn for_sample(seed: u64, pixel: u64, sample: u32, _sample_count: u32) -> HaltonSampler {
+        HaltonSampler {
+            rng: Pcg32::for_sample(seed, pixel, sample),
+            seed,
+            pixel,
+            sample,
+            dimension: 0,
+        }
+    }
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        match PRIMES.get(dimension as usize) {
+            Some(base) => {
+                let offset = to_unit(scramble_seed(self.seed, self.pixel, dimension) as u32);
+                let value = radical_inverse(self.sample, *base) + offset;
+                // wrap around, guarding against rounding up to exactly 1
+                (value - value.floor()).min(1.0 - f64::EPSILON)
+            }
+            None => self.rng.next_f64(),
+        }
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+/// Element `index` of the first (van der Corput) or second Sobol dimension, as 32 fixed-point bits
+fn sobol(index: u32, dimension: usize) -> u32 {
+    if dimension == 0 {
+        return index.reverse_bits();
+    }
+    // direction numbers of the second dimension, from the primitive polynomial x + 1
+    let mut direction = 1u32 << 31;
+    let mut result = 0;
+    for bit in 0..32 {
+        if (index >> bit) & 1 == 1 {
+            result ^= direction;
+        }
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+/// Burley's improved Laine-Karras hash, which only lets bits affect more significant ones
+fn laine_karras_permutation(mut value: u32, seed: u32) -> u32 {
+    value = value.wrapping_add(seed);
+    value ^= value.wrapping_mul(0x6c50b47c);
+    value ^= value.wrapping_mul(0xb82f1e52);
+    value ^= value.wrapping_mul(0xc7afe638);
+    value ^= value.wrapping_mul(0x8d22f6e6);
+    value
+}
+
+/// Owen scrambling of fixed-point bits: every bit is flipped depending on the bits above it
+fn nested_uniform_scramble(value: u32, seed: u32) -> u32 {
+    laine_karras_permutation(value.reverse_bits(), seed).reverse_bits()
+}
+
+/// # Owen-scrambled Sobol sequence
+/// Burley's "Practical Hash-based Owen Scrambling": every pair of dimensions is the two-dimensional Sobol sequence,
+/// with its own shuffle of the sample order and its own Owen scrambling per pixel, which decorrelates the pairs while
+/// keeping each one well stratified at any power of two samples
+pub(crate) struct SobolSampler {
+    seed: u64,
+    pixel: u64,
+    sample: u32,
+    dimension: u32,
+}
+
+impl SobolSampler {
+    fn scrambled(&mut self) -> (u32, u32) {
+        let seed = scramble_seed(self.seed, self.pixel, self.dimension);
+        self.dimension += 1;
+        let index = nested_uniform_scramble(self.sample, seed as u32);
+        (
+            nested_uniform_scramble(sobol(index, 0), splitmix64(seed) as u32),
+            nested_uniform_scramble(sobol(index, 1), (splitmix64(seed) >> 32) as u32),
+        )
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn for_sample(seed: u64, pixel: u64, sample: u32, _sample_count: u32) -> SobolSampler {
+        SobolSampler {
+            seed,
+            pixel,
+            sample,
+            dimension: 0,
+        }
+    }
+    fn get_1d(&mut self) -> f64 {
+        to_unit(self.scrambled().0)
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (x, y) = self.scrambled();
+        (to_unit(x), to_unit(y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mean squared error of estimating the integral of a smooth function over the unit square with `samples`
+    /// samples, over many pixels
+    fn integration_error<S: Sampler>(samples: u32) -> f64 {
+        let function = |x: f64, y: f64| (x*3.0).sin()*y*y + x;
+        let exact = (1.0 - 3f64.cos())/9.0 + 0.5;
+        let pixels = 200;
+        let mut squared_error = 0.0;
+        for pixel in 0..pixels {
+            let mut estimate = 0.0;
+            for sample in 0..samples {
+                let mut sampler = S::for_sample(5, pixel, sample, samples);
+                // skip the first pair so the test covers later dimensions
+                sampler.get_2d();
+                let (x, y) = sampler.get_2d();
+                assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+                estimate += function(x, y)/samples as f64;
+            }
+            squared_error += (estimate - exact).powi(2)/pixels as f64;
+        }
+        squared_error
+    }
+
+    #[test]
+    fn sampler_convergence_test() {
+        let independent = integration_error::<IndependentSampler>(64);
+        for (name, error) in [
+            ("stratified", integration_error::<StratifiedSampler>(64)),
+            ("halton", integration_error::<HaltonSampler>(64)),
+            ("sobol", integration_error::<SobolSampler>(64)),
+        ] {
+            assert!(error*4.0 < independent, "{name}: {error} against {independent}");
+        }
+    }
+
+    #[test]
+    fn permute_test() {
+        for length in [1, 7, 64, 100] {
+            let mut values: Vec<u32> = (0..length).map(|index| permute(index, length, 12345)).collect();
+            values.sort();
+            assert_eq!(values, (0..length).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn sobol_stratification_test() {
+        // every power of two prefix of a scrambled Sobol pair has one point in each of the elementary intervals
+        let mut cells = [0; 16];
+        for sample in 0..16 {
+            let mut sampler = SobolSampler::for_sample(9, 3, sample, 16);
+            let (x, y) = sampler.get_2d();
+            cells[(x*4.0) as usize + 4*(y*4.0) as usize] += 1;
+        }
+        assert!(cells.iter().all(|count| *count == 1));
+        assert_eq!(radical_inverse(6, 2), 0.375);
+    }
+}
@@ -0,0 +1,927 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::obj_loader::{load_obj_fingerprinted, ObjError, ObjOptions};
+use crate::camera::Camera;
+use crate::checkpoint::{fnv1a, FNV_OFFSET};
+use crate::error::Error;
+use crate::filter::Filter;
+use crate::material::Material;
+use crate::rendering_equation::{MisHeuristic, RenderSettings};
+use crate::sampler::SamplerKind;
+use crate::tonemapping::ToneMapping;
+use crate::type_structs::{AxisAlignedBox, DiffuseColour, Disk, MeshError, Plane, Quad, RenderScene, SceneObject, Sphere, Triangle, TriangleMesh, Vec3D};
+
+// Declarative scene files
+//
+// A scene file is TOML and looks like:
+//
+// [camera]                        # every field is optional, the defaults are shown
+// eye = [0.0, 0.0, 0.0]
+// target = [0.0, 0.0, -1.0]
+// up = [0.0, 1.0, 0.0]
+// vertical_fov = 90.0             # full vertical opening angle, in degrees
+// aspect_ratio = 1.0              # width / height, defaults to that of the image
+// aperture_radius = 0.0           # lens radius for depth of field, 0 is a pinhole camera
+// focus_distance = 1.0            # distance to the plane in focus, defaults to the distance to target
+//
+// [render]
+// width = 800
+// height = 800
+// samples = 8
+// max_depth = 64                  # optional hard limit on path length
+// mis_heuristic = "power"         # "power" or "balance", weighting of BSDF against light sampling
+// sampler = "sobol"              # "independent", "stratified", "halton" or "sobol"
+// filter = "box"                 # "box", "tent", "gaussian", "mitchell" or "lanczos" reconstruction filter
+// filter_radius = 0.5             # optional, in pixels, defaults to 0.5, 1, 1.5, 2 and 3 for the filters above
+// adaptive_threshold = 0.02       # optional, stop sampling a pixel once its relative standard error is below this
+// sample_map = "samples.png"      # optional, image of the number of samples taken per pixel
+// exposure = 0.0                  # in stops, for 8-bit output
+// tone_mapping = "aces"           # "clamp", "reinhard", "aces" or "uncharted2", for 8-bit output
+// progressive = false             # take one sample per pixel over the whole image at a time
+// snapshot_passes = 16            # optional, save the progressive image to output every 16 passes
+// snapshot_seconds = 60.0         # optional, save the progressive image to output at least a minute apart
+// checkpoint = "render.ckpt"      # optional, keep the progressive render's samples here to resume it with --resume
+// output = "Converged.png"
+//
+// Every object has a material, given as an inline table whose type picks the remaining fields:
+//   { type = "diffuse", colour = [6.0, 6.0, 6.0] }                    # colours range from 0 to 12
+//   { type = "specular" }
+//   { type = "glossy", colour = [10.0, 10.0, 10.0], exponent = 100.0 }  # higher exponents are sharper
+//   { type = "refractive", refractive_index = 1.5 }
+//   { type = "emissive", colour = [12.0, 12.0, 12.0], strength = 20.0 }
+//
+// [[sphere]]
+// center = [0.0, 1.9, -3.0]
+// radius = 0.5
+// material = { type = "emissive", colour = [12.0, 12.0, 12.0], strength = 20.0 }
+//
+// [[plane]]
+// normal = [0.0, 1.0, 0.0]        # normalized on load
+// distance_to_origin = 2.5
+// material = { type = "diffuse", colour = [6.0, 6.0, 6.0] }
+//
+// [[box]]                         # faces parallel to the coordinate planes
+// min = [-1.0, -2.5, -5.0]        # the corner with the smallest coordinates
+// max = [1.0, -1.5, -3.0]         # the opposite corner, larger along every axis
+// material = { type = "diffuse", colour = [6.0, 6.0, 6.0] }
+//
+// [[quad]]                        # parallelogram, emissive quads are sampled as area lights
+// corner = [-0.5, 2.49, -3.5]
+// edge1 = [1.0, 0.0, 0.0]         # the corners are corner, corner + edge1, corner + edge1 + edge2 and corner + edge2
+// edge2 = [0.0, 0.0, 1.0]         # edge1 x edge2 is the normal, pointing down here
+// material = { type = "emissive", colour = [12.0, 12.0, 12.0], strength = 10.0 }
+//
+// [[disk]]                        # emissive disks are sampled as area lights
+// center = [0.0, 2.49, -3.0]
+// normal = [0.0, -1.0, 0.0]       # normalized on load
+// radius = 0.5
+// material = { type = "emissive", colour = [12.0, 12.0, 12.0], strength = 10.0 }
+//
+// [[triangle]]
+// vertices = [[-1.0, -1.0, -4.0], [1.0, -1.0, -4.0], [0.0, 1.0, -4.0]]   # counter-clockwise faces the viewer
+// normals = [[0.0, 0.0, 1.0], [0.0, 0.0, 1.0], [0.0, 0.0, 1.0]]       # optional vertex normals
+// uvs = [[0.0, 0.0], [1.0, 0.0], [0.5, 1.0]]                           # optional texture coordinates
+// material = { type = "diffuse", colour = [6.0, 6.0, 6.0] }
+//
+// [[mesh]]
+// positions = [[0.0, 0.0, -4.0], [1.0, 0.0, -4.0], [1.0, 1.0, -4.0], [0.0, 1.0, -4.0]]
+// indices = [[0, 1, 2], [0, 2, 3]]                                     # faces, indexing positions
+// normals = [...]                                                      # optional, one per position
+// uvs = [...]                                                          # optional, one per position
+// material = { type = "specular" }
+//
+// [[obj]]
+// path = "models/teapot.obj"      # relative to the scene file, materials come from its mtllib
+// scale = 1.0                     # optional uniform scale, applied first
+// translate = [0.0, -2.5, -4.0]   # optional
+// emission_scale = 1.0            # optional multiplier for MTL Ke
+
+/// # Errors produced while loading a scene file
+/// Line and column numbers are 1-based
+#[derive(Debug)]
+pub enum SceneError {
+    /// The scene file could not be read
+    Io { path: String, source: std::io::Error },
+    /// The file is not valid TOML or does not match the scene layout
+    Parse { path: String, line: usize, column: usize, message: String },
+    /// A field parsed fine but holds a value the renderer cannot use
+    InvalidField { path: String, line: usize, field: String, message: String },
+    /// A model referenced by the scene could not be imported
+    Import { path: String, line: usize, source: ObjError },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "{path}: {source}"),
+            SceneError::Parse { path, line, column, message } => write!(f, "{path}:{line}:{column}: {message}"),
+            SceneError::InvalidField { path, line, field, message } => write!(f, "{path}:{line}: {field}: {message}"),
+            SceneError::Import { path, line, source } => write!(f, "{path}:{line}: could not import model: {source}"),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Io { source, .. } => Some(source),
+            SceneError::Import { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// A scene together with the settings it should be rendered with, the name of every object in the scene file (such
+/// as `sphere[0]`, by index into objects_list), and a fingerprint of the scene description that leaves out the
+/// `[render]` table but includes the imported OBJ and MTL files, for recognising checkpoints of the same scene
+pub struct LoadedScene {
+    pub scene: RenderScene,
+    pub settings: RenderSettings,
+    pub object_names: Vec<String>,
+    pub fingerprint: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    #[serde(default)]
+    camera: CameraDescription,
+    #[serde(default)]
+    render: RenderDescription,
+    #[serde(default)]
+    sphere: Vec<Spanned<SphereDescription>>,
+    #[serde(default)]
+    plane: Vec<Spanned<PlaneDescription>>,
+    #[serde(default, rename = "box")]
+    boxes: Vec<Spanned<BoxDescription>>,
+    #[serde(default)]
+    quad: Vec<Spanned<QuadDescription>>,
+    #[serde(default)]
+    disk: Vec<Spanned<DiskDescription>>,
+    #[serde(default)]
+    triangle: Vec<Spanned<TriangleDescription>>,
+    #[serde(default)]
+    mesh: Vec<Spanned<MeshDescription>>,
+    #[serde(default)]
+    obj: Vec<Spanned<ObjDescription>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct CameraDescription {
+    eye: Option<[f64; 3]>,
+    target: Option<[f64; 3]>,
+    up: Option<[f64; 3]>,
+    vertical_fov: Option<Spanned<f64>>,
+    aspect_ratio: Option<Spanned<f64>>,
+    aperture_radius: Option<Spanned<f64>>,
+    focus_distance: Option<Spanned<f64>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RenderDescription {
+    width: Option<Spanned<i32>>,
+    height: Option<Spanned<i32>>,
+    samples: Option<Spanned<i32>>,
+    max_depth: Option<Spanned<i32>>,
+    output: Option<String>,
+    mis_heuristic: Option<Spanned<String>>,
+    sampler: Option<Spanned<String>>,
+    filter: Option<Spanned<String>>,
+    filter_radius: Option<Spanned<f64>>,
+    adaptive_threshold: Option<Spanned<f64>>,
+    sample_map: Option<String>,
+    exposure: Option<f64>,
+    tone_mapping: Option<Spanned<String>>,
+    progressive: Option<bool>,
+    snapshot_passes: Option<Spanned<i32>>,
+    snapshot_seconds: Option<Spanned<f64>>,
+    checkpoint: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SphereDescription {
+    center: [f64; 3],
+    radius: f64,
+    material: MaterialDescription,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlaneDescription {
+    normal: [f64; 3],
+    distance_to_origin: f64,
+    material: MaterialDescription,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BoxDescription {
+    min: [f64; 3],
+    max: [f64; 3],
+    material: MaterialDescription,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QuadDescription {
+    corner: [f64; 3],
+    edge1: [f64; 3],
+    edge2: [f64; 3],
+    material: MaterialDescription,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DiskDescription {
+    center: [f64; 3],
+    normal: [f64; 3],
+    radius: f64,
+    material: MaterialDescription,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TriangleDescription {
+    vertices: [[f64; 3]; 3],
+    normals: Option<[[f64; 3]; 3]>,
+    uvs: Option<[[f64; 2]; 3]>,
+    material: MaterialDescription,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshDescription {
+    positions: Vec<[f64; 3]>,
+    indices: Vec<[usize; 3]>,
+    #[serde(default)]
+    normals: Vec<[f64; 3]>,
+    #[serde(default)]
+    uvs: Vec<[f64; 2]>,
+    material: MaterialDescription,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum MaterialDescription {
+    Diffuse { colour: [f64; 3] },
+    Specular,
+    Glossy { colour: [f64; 3], exponent: f64 },
+    Refractive { refractive_index: f64 },
+    Emissive { colour: [f64; 3], strength: f64 },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ObjDescription {
+    path: String,
+    #[serde(default = "one")]
+    scale: f64,
+    #[serde(default)]
+    translate: [f64; 3],
+    #[serde(default = "one")]
+    emission_scale: f64,
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+fn vec3d(value: [f64; 3]) -> Vec3D {
+    Vec3D { x: value[0], y: value[1], z: value[2] }
+}
+
+fn colour(value: [f64; 3]) -> DiffuseColour {
+    DiffuseColour { r: value[0], g: value[1], b: value[2] }
+}
+
+/// Convert a byte offset into the source to a 1-based (line, column) pair
+fn line_and_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, column)
+}
+
+/// Helper carrying the file name and source text so errors can point at the offending line
+struct ErrorContext<'a> {
+    path: &'a str,
+    source: &'a str,
+}
+
+impl ErrorContext<'_> {
+    /// Build an InvalidField error, pointing at the line of the field inside the spanned table if it can be found
+    fn invalid(&self, span: Range<usize>, field: String, message: &str) -> SceneError {
+        let key = field.rsplit('.').next().unwrap_or(&field);
+        let table = &self.source[span.start.min(self.source.len())..span.end.min(self.source.len())];
+        let mut offset = span.start;
+        for line in table.split_inclusive('\n') {
+            let trimmed = line.trim_start();
+            if trimmed.starts_with(key) && trimmed[key.len()..].trim_start().starts_with('=') {
+                break;
+            }
+            offset += line.len();
+        }
+        if offset >= span.end {
+            offset = span.start;
+        }
+        SceneError::InvalidField {
+            path: self.path.to_string(),
+            line: line_and_column(self.source, offset).0,
+            field,
+            message: message.to_string(),
+        }
+    }
+
+    /// Check the material settings of an object and build its Material
+    fn material(&self, span: &Range<usize>, name: &str, description: &MaterialDescription) -> Result<Material, SceneError> {
+        let field = format!("{name}.material");
+        Ok(match description {
+            MaterialDescription::Diffuse { colour: value } => {
+                if value.iter().any(|component| *component < 0.0) {
+                    return Err(self.invalid(span.clone(), field, "colour must not be negative"));
+                }
+                Material::Diffuse { colour: colour(*value) }
+            }
+            MaterialDescription::Specular => Material::Specular,
+            MaterialDescription::Glossy { colour: value, exponent } => {
+                if value.iter().any(|component| *component < 0.0) {
+                    return Err(self.invalid(span.clone(), field, "colour must not be negative"));
+                }
+                if *exponent < 0.0 {
+                    return Err(self.invalid(span.clone(), field, "exponent must not be negative"));
+                }
+                Material::Glossy { colour: colour(*value), exponent: *exponent }
+            }
+            MaterialDescription::Refractive { refractive_index } => {
+                if !(*refractive_index > 0.0 && refractive_index.is_finite()) {
+                    return Err(self.invalid(span.clone(), field, "refractive_index must be a finite number greater than zero"));
+                }
+                Material::Refractive { refractive_index: *refractive_index }
+            }
+            MaterialDescription::Emissive { colour: value, strength } => {
+                if value.iter().any(|component| *component < 0.0) {
+                    return Err(self.invalid(span.clone(), field, "colour must not be negative"));
+                }
+                if *strength < 0.0 {
+                    return Err(self.invalid(span.clone(), field, "strength must not be negative"));
+                }
+                Material::Emissive { colour: colour(*value), strength: *strength }
+            }
+        })
+    }
+}
+
+/// Build a scene and its render settings from the text of a scene file.
+/// `path` is only used to label errors
+pub fn parse_scene(source: &str, path: &str) -> Result<LoadedScene, SceneError> {
+    let context = ErrorContext { path, source };
+    let file: SceneFile = toml::from_str(source).map_err(|error| {
+        let (line, column) = line_and_column(source, error.span().map_or(0, |span| span.start));
+        SceneError::Parse { path: path.to_string(), line, column, message: error.message().to_string() }
+    })?;
+
+    // render settings, anything not given falls back to the defaults
+    let mut settings = RenderSettings::default();
+    for (name, value, target) in [
+        ("render.width", &file.render.width, &mut settings.width),
+        ("render.height", &file.render.height, &mut settings.height),
+        ("render.samples", &file.render.samples, &mut settings.samples),
+        ("render.max_depth", &file.render.max_depth, &mut settings.max_depth),
+    ] {
+        if let Some(value) = value {
+            if *value.get_ref() <= 0 {
+                return Err(context.invalid(value.span(), name.to_string(), "must be greater than zero"));
+            }
+            *target = *value.get_ref();
+        }
+    }
+    if let Some(output) = file.render.output {
+        settings.output = output;
+    }
+    if let Some(heuristic) = &file.render.mis_heuristic {
+        settings.mis_heuristic = match heuristic.get_ref().as_str() {
+            "balance" => MisHeuristic::Balance,
+            "power" => MisHeuristic::Power,
+            _ => return Err(context.invalid(heuristic.span(), "render.mis_heuristic".to_string(), "must be \"balance\" or \"power\"")),
+        };
+    }
+    if let Some(sampler) = &file.render.sampler {
+        settings.sampler = match sampler.get_ref().as_str() {
+            "independent" => SamplerKind::Independent,
+            "stratified" => SamplerKind::Stratified,
+            "halton" => SamplerKind::Halton,
+            "sobol" => SamplerKind::Sobol,
+            _ => return Err(context.invalid(sampler.span(), "render.sampler".to_string(), "must be \"independent\", \"stratified\", \"halton\" or \"sobol\"")),
+        };
+    }
+    if let Some(filter) = &file.render.filter {
+        settings.filter = match filter.get_ref().as_str() {
+            "box" => Filter::Box,
+            "tent" => Filter::Tent,
+            "gaussian" => Filter::Gaussian,
+            "mitchell" => Filter::Mitchell,
+            "lanczos" => Filter::Lanczos,
+            _ => return Err(context.invalid(filter.span(), "render.filter".to_string(), "must be \"box\", \"tent\", \"gaussian\", \"mitchell\" or \"lanczos\"")),
+        };
+    }
+    if let Some(radius) = &file.render.filter_radius {
+        if *radius.get_ref() <= 0.0 {
+            return Err(context.invalid(radius.span(), "render.filter_radius".to_string(), "must be greater than zero"));
+        }
+        settings.filter_radius = Some(*radius.get_ref());
+    }
+    if let Some(threshold) = &file.render.adaptive_threshold {
+        if *threshold.get_ref() <= 0.0 {
+            return Err(context.invalid(threshold.span(), "render.adaptive_threshold".to_string(), "must be greater than zero"));
+        }
+        settings.adaptive_threshold = Some(*threshold.get_ref());
+    }
+    settings.sample_map = file.render.sample_map;
+    if let Some(snapshot_passes) = &file.render.snapshot_passes {
+        if *snapshot_passes.get_ref() <= 0 {
+            return Err(context.invalid(snapshot_passes.span(), "render.snapshot_passes".to_string(), "must be greater than zero"));
+        }
+        settings.snapshot_passes = Some(*snapshot_passes.get_ref());
+    }
+    if let Some(snapshot_seconds) = &file.render.snapshot_seconds {
+        if *snapshot_seconds.get_ref() <= 0.0 {
+            return Err(context.invalid(snapshot_seconds.span(), "render.snapshot_seconds".to_string(), "must be greater than zero"));
+        }
+        settings.snapshot_seconds = Some(*snapshot_seconds.get_ref());
+    }
+    settings.checkpoint = file.render.checkpoint;
+    // asking for snapshots or checkpoints only makes sense for a progressive render
+    settings.progressive = file.render.progressive.unwrap_or(settings.snapshot_passes.is_some() || settings.snapshot_seconds.is_some() || settings.checkpoint.is_some());
+    if let Some(exposure) = file.render.exposure {
+        settings.exposure = exposure;
+    }
+    if let Some(tone_mapping) = &file.render.tone_mapping {
+        settings.tone_mapping = match tone_mapping.get_ref().as_str() {
+            "clamp" => ToneMapping::Clamp,
+            "reinhard" => ToneMapping::Reinhard,
+            "aces" => ToneMapping::Aces,
+            "uncharted2" => ToneMapping::Uncharted2,
+            _ => return Err(context.invalid(tone_mapping.span(), "render.tone_mapping".to_string(), "must be \"clamp\", \"reinhard\", \"aces\" or \"uncharted2\"")),
+        };
+    }
+
+    // camera, anything not given falls back to the original demo camera
+    let mut camera = Camera::default();
+    if let Some(eye) = file.camera.eye {
+        camera.eye = vec3d(eye);
+    }
+    if let Some(target) = file.camera.target {
+        camera.target = vec3d(target);
+    }
+    if let Some(up) = file.camera.up {
+        camera.up = vec3d(up);
+    }
+    if let Some(vertical_fov) = &file.camera.vertical_fov {
+        let degrees = *vertical_fov.get_ref();
+        if degrees <= 0.0 || degrees >= 180.0 {
+            return Err(context.invalid(vertical_fov.span(), "camera.vertical_fov".to_string(), "must be between 0 and 180 degrees"));
+        }
+        camera.vertical_fov = degrees * PI / 180.0;
+    }
+    if let Some(aspect_ratio) = &file.camera.aspect_ratio {
+        if *aspect_ratio.get_ref() <= 0.0 {
+            return Err(context.invalid(aspect_ratio.span(), "camera.aspect_ratio".to_string(), "must be greater than zero"));
+        }
+        camera.aspect_ratio = Some(*aspect_ratio.get_ref());
+    }
+    if let Some(aperture_radius) = &file.camera.aperture_radius {
+        if *aperture_radius.get_ref() < 0.0 {
+            return Err(context.invalid(aperture_radius.span(), "camera.aperture_radius".to_string(), "must not be negative"));
+        }
+        camera.aperture_radius = *aperture_radius.get_ref();
+    }
+    if let Some(focus_distance) = &file.camera.focus_distance {
+        if *focus_distance.get_ref() <= 0.0 {
+            return Err(context.invalid(focus_distance.span(), "camera.focus_distance".to_string(), "must be greater than zero"));
+        }
+        camera.focus_distance = Some(*focus_distance.get_ref());
+    }
+    if camera.frame(settings.width, settings.height).is_none() {
+        return Err(SceneError::InvalidField {
+            path: path.to_string(),
+            line: line_and_column(source, source.find("[camera]").unwrap_or(0)).0,
+            field: "camera".to_string(),
+            message: "eye and target must differ and up must not be parallel to the viewing direction".to_string(),
+        });
+    }
+
+    let mut objects_list: Vec<Box<dyn SceneObject>> = Vec::new();
+    let mut object_names = Vec::new();
+    let mut object_spans = Vec::new();
+    for (i, description) in file.sphere.iter().enumerate() {
+        let span = description.span();
+        let sphere = description.get_ref();
+        let name = format!("sphere[{i}]");
+        if !(sphere.radius > 0.0 && sphere.radius.is_finite()) {
+            return Err(context.invalid(span, format!("{name}.radius"), "must be a finite number greater than zero"));
+        }
+        let material = context.material(&span, &name, &sphere.material)?;
+        object_spans.push(span);
+        object_names.push(name);
+        objects_list.push(Box::new(Sphere {
+            center: vec3d(sphere.center),
+            radius: sphere.radius,
+            material,
+        }));
+    }
+    for (i, description) in file.plane.iter().enumerate() {
+        let span = description.span();
+        let plane = description.get_ref();
+        let name = format!("plane[{i}]");
+        if vec3d(plane.normal).length() == 0.0 {
+            return Err(context.invalid(span, format!("{name}.normal"), "must not be the zero vector"));
+        }
+        let material = context.material(&span, &name, &plane.material)?;
+        let mut new_plane = Plane {
+            normal: vec3d(plane.normal),
+            distance_to_origin: plane.distance_to_origin,
+            material,
+        };
+        new_plane.normalize_plane();
+        object_spans.push(span);
+        object_names.push(name);
+        objects_list.push(Box::new(new_plane));
+    }
+    for (i, description) in file.boxes.iter().enumerate() {
+        let span = description.span();
+        let description = description.get_ref();
+        let name = format!("box[{i}]");
+        if (0..3).any(|axis| description.min[axis] >= description.max[axis]) {
+            return Err(context.invalid(span, format!("{name}.max"), "must be larger than min along every axis"));
+        }
+        let material = context.material(&span, &name, &description.material)?;
+        object_spans.push(span);
+        object_names.push(name);
+        objects_list.push(Box::new(AxisAlignedBox {
+            min: vec3d(description.min),
+            max: vec3d(description.max),
+            material,
+        }));
+    }
+    for (i, description) in file.quad.iter().enumerate() {
+        let span = description.span();
+        let quad = description.get_ref();
+        let name = format!("quad[{i}]");
+        if vec3d(quad.edge1).cross(&vec3d(quad.edge2)).length() == 0.0 {
+            return Err(context.invalid(span, format!("{name}.edge2"), "must not be zero or parallel to edge1"));
+        }
+        let material = context.material(&span, &name, &quad.material)?;
+        object_spans.push(span);
+        object_names.push(name);
+        objects_list.push(Box::new(Quad {
+            corner: vec3d(quad.corner),
+            edge1: vec3d(quad.edge1),
+            edge2: vec3d(quad.edge2),
+            material,
+        }));
+    }
+    for (i, description) in file.disk.iter().enumerate() {
+        let span = description.span();
+        let disk = description.get_ref();
+        let name = format!("disk[{i}]");
+        if !(disk.radius > 0.0 && disk.radius.is_finite()) {
+            return Err(context.invalid(span, format!("{name}.radius"), "must be a finite number greater than zero"));
+        }
+        let mut normal = vec3d(disk.normal);
+        if normal.length() == 0.0 {
+            return Err(context.invalid(span, format!("{name}.normal"), "must not be the zero vector"));
+        }
+        normal.norm();
+        let material = context.material(&span, &name, &disk.material)?;
+        object_spans.push(span);
+        object_names.push(name);
+        objects_list.push(Box::new(Disk {
+            center: vec3d(disk.center),
+            normal,
+            radius: disk.radius,
+            material,
+        }));
+    }
+    for (i, description) in file.triangle.iter().enumerate() {
+        let span = description.span();
+        let triangle = description.get_ref();
+        let name = format!("triangle[{i}]");
+        let vertices = triangle.vertices.map(vec3d);
+        if vertices[1].subtract(&vertices[0]).cross(&vertices[2].subtract(&vertices[0])).length() == 0.0 {
+            return Err(context.invalid(span, format!("{name}.vertices"), "must not be collinear"));
+        }
+        let material = context.material(&span, &name, &triangle.material)?;
+        object_spans.push(span);
+        object_names.push(name);
+        objects_list.push(Box::new(Triangle {
+            vertices,
+            normals: triangle.normals.map(|normals| normals.map(|normal| {
+                let mut normal = vec3d(normal);
+                normal.norm();
+                normal
+            })),
+            uvs: triangle.uvs,
+            material,
+        }));
+    }
+    for (i, description) in file.mesh.iter().enumerate() {
+        let span = description.span();
+        let mesh = description.get_ref();
+        let name = format!("mesh[{i}]");
+        let material = context.material(&span, &name, &mesh.material)?;
+        let normals = mesh.normals.iter().map(|normal| {
+            let mut normal = vec3d(*normal);
+            normal.norm();
+            normal
+        }).collect();
+        let positions = mesh.positions.iter().map(|position| vec3d(*position)).collect();
+        let triangle_mesh = TriangleMesh::new(positions, mesh.indices.clone(), material)
+            .and_then(|built| built.with_normals(normals))
+            .and_then(|built| built.with_uvs(mesh.uvs.clone()))
+            .map_err(|error| {
+                let field = match error {
+                    MeshError::NoFaces | MeshError::MissingPosition { .. } => "indices",
+                    MeshError::CountMismatch { field, .. } | MeshError::NonFinite { field, .. } => field,
+                    MeshError::ZeroNormal { .. } => "normals",
+                };
+                context.invalid(span.clone(), format!("{name}.{field}"), &error.to_string())
+            })?;
+        object_spans.push(span);
+        object_names.push(name);
+        objects_list.push(Box::new(triangle_mesh));
+    }
+    // models are looked up relative to the scene file, and their contents are part of the scene's fingerprint
+    let mut fingerprint = scene_fingerprint(source);
+    let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+    for (i, description) in file.obj.iter().enumerate() {
+        let span = description.span();
+        let obj = description.get_ref();
+        let name = format!("obj[{i}]");
+        if obj.scale <= 0.0 {
+            return Err(context.invalid(span, format!("{name}.scale"), "must be greater than zero"));
+        }
+        if obj.emission_scale < 0.0 {
+            return Err(context.invalid(span, format!("{name}.emission_scale"), "must not be negative"));
+        }
+        let options = ObjOptions {
+            scale: obj.scale,
+            translate: vec3d(obj.translate),
+            emission_scale: obj.emission_scale,
+        };
+        let (meshes, model_fingerprint) = load_obj_fingerprinted(&directory.join(&obj.path), &options).map_err(|error| SceneError::Import {
+            path: path.to_string(),
+            line: line_and_column(source, span.start).0,
+            source: error,
+        })?;
+        fingerprint = fnv1a(&model_fingerprint.to_le_bytes(), fingerprint);
+        for mesh in meshes {
+            object_spans.push(span.clone());
+            object_names.push(name.clone());
+            objects_list.push(Box::new(mesh));
+        }
+    }
+
+    // the checks above point at the offending field, RenderScene::new refuses anything they let through (such as NaN
+    // coordinates or zero vertex normals) and imported meshes, reported against the table the object came from
+    let mut scene = RenderScene::new(objects_list).map_err(|error| match error {
+        Error::InvalidObject { index, message } => context.invalid(object_spans[index].clone(), object_names[index].clone(), &message),
+        error => context.invalid(0..0, "scene".to_string(), &error.to_string()),
+    })?;
+    scene.camera = camera;
+    Ok(LoadedScene {
+        scene,
+        settings,
+        object_names,
+        fingerprint,
+    })
+}
+
+/// Hash of everything in a scene file but its [render] table, so changing the sample count or output keeps it.
+/// The table is re-serialised first, which drops comments and formatting
+fn scene_fingerprint(source: &str) -> u64 {
+    let mut table: toml::Table = toml::from_str(source).unwrap_or_default();
+    table.remove("render");
+    fnv1a(table.to_string().as_bytes(), FNV_OFFSET)
+}
+
+/// Read a scene file from disk and build the scene it describes
+pub fn load_scene(path: &Path) -> Result<LoadedScene, SceneError> {
+    let path_name = path.display().to_string();
+    let source = fs::read_to_string(path).map_err(|source| SceneError::Io { path: path_name.clone(), source })?;
+    parse_scene(&source, &path_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENE: &str = r#"
+[camera]
+eye = [0.0, 1.0, 0.0]
+vertical_fov = 60.0
+
+[render]
+width = 64
+height = 32
+samples = 2
+
+[[sphere]]
+center = [0.0, 1.0, -3.0]
+radius = 0.5
+material = { type = "emissive", colour = [12.0, 12.0, 12.0], strength = 100.0 }
+
+[[plane]]
+normal = [0.0, 2.0, 0.0]
+distance_to_origin = 2.5
+material = { type = "diffuse", colour = [6.0, 6.0, 6.0] }
+"#;
+
+    #[test]
+    fn parse_scene_test() {
+        let loaded = parse_scene(SCENE, "test.toml").unwrap();
+        assert_eq!(loaded.scene.objects_list.len(), 2);
+        assert_eq!(loaded.object_names, vec!["sphere[0]", "plane[0]"]);
+        assert_eq!(loaded.settings.width, 64);
+        assert_eq!(loaded.settings.height, 32);
+        assert_eq!(loaded.settings.samples, 2);
+        assert_eq!(loaded.settings.output, "Converged.png");
+        assert!((loaded.scene.camera.vertical_fov - PI/3.0).abs() < 1e-12);
+        assert_eq!(loaded.scene.camera.eye.y, 1.0);
+        assert_eq!(loaded.scene.camera.target.z, -1.0);
+        assert_eq!(loaded.scene.objects_list[0].material().emitted().r, 100.0);
+        // plane normals are normalized on load
+        let normal = loaded.scene.objects_list[1].normal(&Vec3D { x: 0.0, y: 0.0, z: 0.0 });
+        assert_eq!(normal.y, 1.0);
+    }
+
+    #[test]
+    fn scene_fingerprint_test() {
+        let fingerprint = parse_scene(SCENE, "test.toml").unwrap().fingerprint;
+        // render settings and comments do not change the scene
+        let more_samples = SCENE.replace("samples = 2", "samples = 200\n# more samples");
+        assert_eq!(parse_scene(&more_samples, "test.toml").unwrap().fingerprint, fingerprint);
+        let moved = SCENE.replace("center = [0.0, 1.0, -3.0]", "center = [0.0, 1.0, -3.5]");
+        assert_ne!(parse_scene(&moved, "test.toml").unwrap().fingerprint, fingerprint);
+
+        // editing an imported model changes the fingerprint even though the scene file is unchanged
+        let model = std::env::temp_dir().join(format!("rusty_render_test_{}.obj", std::process::id()));
+        let source = format!("{SCENE}\n[[obj]]\npath = '{}'\n", model.display());
+        fs::write(&model, "v 0 0 -1\nv 1 0 -1\nv 0 1 -1\nf 1 2 3\n").unwrap();
+        let imported = parse_scene(&source, "test.toml").unwrap().fingerprint;
+        fs::write(&model, "v 0 0 -1\nv 2 0 -1\nv 0 1 -1\nf 1 2 3\n").unwrap();
+        let edited = parse_scene(&source, "test.toml").unwrap().fingerprint;
+        fs::remove_file(&model).unwrap();
+        assert_ne!(imported, fingerprint);
+        assert_ne!(edited, imported);
+    }
+
+    #[test]
+    fn parse_scene_syntax_error_test() {
+        let source = "[render]\nwidth = 64\nheight = \n";
+        match parse_scene(source, "broken.toml") {
+            Err(SceneError::Parse { line, .. }) => assert_eq!(line, 3),
+            _ => panic!("expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn parse_scene_invalid_field_test() {
+        let source = SCENE.replace("strength = 100.0", "strength = -1.0");
+        match parse_scene(&source, "invalid.toml") {
+            Err(SceneError::InvalidField { line, field, .. }) => {
+                assert_eq!(line, 14);
+                assert_eq!(field, "sphere[0].material");
+            }
+            _ => panic!("expected an invalid field error"),
+        }
+        // NaN would pass a plain `<= 0.0` test
+        let nan_fields = [
+            (SCENE.replace("radius = 0.5", "radius = nan"), "sphere[0].radius"),
+            (SCENE.replace("type = \"emissive\", colour = [12.0, 12.0, 12.0], strength = 100.0", "type = \"refractive\", refractive_index = nan"), "sphere[0].material"),
+        ];
+        for (source, expected) in nan_fields {
+            match parse_scene(&source, "invalid.toml") {
+                Err(SceneError::InvalidField { field, .. }) => assert_eq!(field, expected),
+                _ => panic!("expected an invalid field error for {expected}"),
+            }
+        }
+        // fields belonging to another material type are rejected
+        let source = SCENE.replace("strength = 100.0", "refractive_index = 1.5");
+        match parse_scene(&source, "invalid.toml") {
+            Err(SceneError::Parse { line, .. }) => assert_eq!(line, 14),
+            _ => panic!("expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn parse_scene_mesh_test() {
+        let source = r#"
+[[triangle]]
+vertices = [[-1.0, -1.0, -4.0], [1.0, -1.0, -4.0], [0.0, 1.0, -4.0]]
+material = { type = "diffuse", colour = [6.0, 6.0, 6.0] }
+
+[[mesh]]
+positions = [[0.0, 0.0, -4.0], [1.0, 0.0, -4.0], [1.0, 1.0, -4.0], [0.0, 1.0, -4.0]]
+indices = [[0, 1, 2], [0, 2, 4]]
+material = { type = "specular" }
+"#;
+        match parse_scene(source, "mesh.toml") {
+            Err(SceneError::InvalidField { line, field, .. }) => {
+                assert_eq!(line, 8);
+                assert_eq!(field, "mesh[0].indices");
+            }
+            _ => panic!("expected an invalid field error"),
+        }
+        let loaded = parse_scene(&source.replace("[0, 2, 4]", "[0, 2, 3]"), "mesh.toml").unwrap();
+        assert_eq!(loaded.scene.objects_list.len(), 2);
+    }
+    #[test]
+    fn parse_scene_area_light_test() {
+        let source = r#"
+[[quad]]
+corner = [-0.5, 2.49, -3.5]
+edge1 = [1.0, 0.0, 0.0]
+edge2 = [0.0, 0.0, 1.0]
+material = { type = "emissive", colour = [12.0, 12.0, 12.0], strength = 10.0 }
+
+[[disk]]
+center = [0.0, 2.49, -3.0]
+normal = [0.0, -2.0, 0.0]
+radius = 0.5
+material = { type = "emissive", colour = [12.0, 12.0, 12.0], strength = 10.0 }
+"#;
+        let loaded = parse_scene(source, "lights.toml").unwrap();
+        assert_eq!(loaded.object_names, vec!["quad[0]", "disk[0]"]);
+        // both are sampled as lights, and disk normals are normalized on load
+        assert_eq!(loaded.scene.lights, vec![0, 1]);
+        assert_eq!(loaded.scene.objects_list[1].normal(&Vec3D {x: 0.0, y: 2.49, z: -3.0}).y, -1.0);
+        match parse_scene(&source.replace("edge2 = [0.0, 0.0, 1.0]", "edge2 = [2.0, 0.0, 0.0]"), "lights.toml") {
+            Err(SceneError::InvalidField { line, field, .. }) => {
+                assert_eq!(line, 5);
+                assert_eq!(field, "quad[0].edge2");
+            }
+            _ => panic!("expected an invalid field error"),
+        }
+        match parse_scene(&source.replace("radius = 0.5", "radius = nan"), "lights.toml") {
+            Err(SceneError::InvalidField { field, .. }) => assert_eq!(field, "disk[0].radius"),
+            _ => panic!("expected an invalid field error"),
+        }
+    }
+
+    #[test]
+    fn parse_scene_checked_objects_test() {
+        // values the loader's own checks let through are refused by RenderScene::new, pointing at the object's table
+        let source = r#"
+[[sphere]]
+center = [0.0, 1.0, -3.0]
+radius = 0.5
+material = { type = "emissive", colour = [12.0, 12.0, 12.0], strength = 100.0 }
+
+[[triangle]]
+vertices = [[0.0, 0.0, -1.0], [1.0, 0.0, -1.0], [0.0, 1.0, -1.0]]
+normals = [[0.0, 0.0, 1.0], [0.0, 0.0, 0.0], [0.0, 0.0, 1.0]]
+material = { type = "diffuse", colour = [6.0, 6.0, 6.0] }
+"#;
+        match parse_scene(source, "checked.toml") {
+            Err(SceneError::InvalidField { line, field, .. }) => {
+                assert_eq!(line, 7);
+                assert_eq!(field, "triangle[0]");
+            }
+            _ => panic!("expected an invalid field error"),
+        }
+        let source = source.replace("[0.0, 0.0, 0.0]", "[0.0, 0.0, 1.0]").replace("[0.0, 1.0, -1.0]]", "[0.0, nan, -1.0]]");
+        assert!(matches!(parse_scene(&source, "checked.toml"), Err(SceneError::InvalidField { .. })));
+        assert!(parse_scene(&source.replace("nan", "1.0"), "checked.toml").is_ok());
+    }
+
+    #[test]
+    fn parse_scene_box_test() {
+        let source = r#"
+[[box]]
+min = [-1.0, -2.5, -5.0]
+max = [1.0, -1.5, -3.0]
+material = { type = "diffuse", colour = [6.0, 6.0, 6.0] }
+"#;
+        let loaded = parse_scene(source, "box.toml").unwrap();
+        assert_eq!(loaded.object_names, vec!["box[0]"]);
+        let bounds = loaded.scene.objects_list[0].bounding_box().unwrap();
+        assert_eq!((bounds.min.y, bounds.max.z), (-2.5, -3.0));
+        match parse_scene(&source.replace("max = [1.0", "max = [-1.0"), "box.toml") {
+            Err(SceneError::InvalidField { line, field, .. }) => {
+                assert_eq!(line, 4);
+                assert_eq!(field, "box[0].max");
+            }
+            _ => panic!("expected an invalid field error"),
+        }
+    }
+}
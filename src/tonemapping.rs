@@ -0,0 +1,90 @@
+// Display transform for 8-bit output
+//
+// Linear radiance is scaled by 2^exposure, compressed into [0, 1] by a tone mapping operator, encoded with the sRGB
+// transfer function and only then quantised. A radiance of 1 maps to white with the clamp operator at exposure 0.
+
+/// # Tone mapping operator compressing linear radiance into the displayable range
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum ToneMapping {
+    /// No compression, values above 1 are clipped
+    Clamp,
+    /// x / (1 + x), never clips but flattens highlights
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve
+    Aces,
+    /// John Hable's filmic curve from Uncharted 2
+    Uncharted2,
+}
+
+/// Hable's curve before normalisation by the white point
+fn uncharted2_curve(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    (x*(a*x + c*b) + d*e)/(x*(a*x + b) + d*f) - e/f
+}
+
+impl ToneMapping {
+    /// Map one linear channel value into [0, 1]
+    pub(crate) fn apply(&self, value: f64) -> f64 {
+        let value = value.max(0.0);
+        let mapped = match self {
+            ToneMapping::Clamp => value,
+            ToneMapping::Reinhard => value/(1.0 + value),
+            ToneMapping::Aces => (value*(2.51*value + 0.03))/(value*(2.43*value + 0.59) + 0.14),
+            ToneMapping::Uncharted2 => {
+                // the curve is designed for an exposure bias of 2 and a linear white point of 11.2
+                uncharted2_curve(2.0*value)/uncharted2_curve(11.2)
+            }
+        };
+        mapped.clamp(0.0, 1.0)
+    }
+}
+
+/// sRGB transfer function, from linear [0, 1] to encoded [0, 1]
+pub(crate) fn srgb_encode(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        12.92*value
+    }else {
+        1.055*value.powf(1.0/2.4) - 0.055
+    }
+}
+
+/// Full display transform of one linear channel value to an 8-bit sRGB value
+pub(crate) fn display_value(value: f64, exposure: f64, tone_mapping: ToneMapping) -> u8 {
+    let encoded = srgb_encode(tone_mapping.apply(value*exposure.exp2()));
+    (encoded*255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tone_mapping_test() {
+        for tone_mapping in [ToneMapping::Clamp, ToneMapping::Reinhard, ToneMapping::Aces, ToneMapping::Uncharted2] {
+            assert!(tone_mapping.apply(0.0).abs() < 0.01, "{tone_mapping:?}");
+            assert!(tone_mapping.apply(-5.0).abs() < 0.01, "{tone_mapping:?}");
+            assert!(tone_mapping.apply(1e6) <= 1.0, "{tone_mapping:?}");
+            // monotonic over the usual range
+            let mut previous = tone_mapping.apply(0.0);
+            for i in 1..200 {
+                let mapped = tone_mapping.apply(i as f64*0.05);
+                assert!(mapped >= previous, "{tone_mapping:?}");
+                previous = mapped;
+            }
+        }
+        assert_eq!(ToneMapping::Reinhard.apply(1.0), 0.5);
+        assert_eq!(ToneMapping::Clamp.apply(2.0), 1.0);
+        assert!((ToneMapping::Uncharted2.apply(5.6) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn display_value_test() {
+        assert_eq!(display_value(0.0, 0.0, ToneMapping::Clamp), 0);
+        assert_eq!(display_value(1.0, 0.0, ToneMapping::Clamp), 255);
+        // linear middle grey is encoded well above half
+        assert_eq!(display_value(0.18, 0.0, ToneMapping::Clamp), 118);
+        // one stop of exposure doubles the linear value
+        assert_eq!(display_value(0.09, 1.0, ToneMapping::Clamp), 118);
+        assert!((srgb_encode(0.001) - 0.01292).abs() < 1e-12);
+    }
+}
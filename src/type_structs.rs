@@ -1,3 +1,13 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::sync::OnceLock;
+
+use crate::bvh::Bvh;
+use crate::camera::{concentric_disk, Camera};
+use crate::error::Error;
+use crate::material::Material;
+use crate::validation::Severity;
+
 pub(crate) static EPSILON: f64 = 0.000001;
 pub(crate) static INFINITY: f64 = f64::MAX;
 
@@ -6,69 +16,208 @@ pub(crate) static INFINITY: f64 = f64::MAX;
 /// # An RGB colour for use in the rendering engine
 /// Contains elements r, g, b each of type u8
 #[derive(Clone,Debug,Copy)]
-pub(crate) struct DiffuseColour {
-    pub(crate) r: f64,
-    pub(crate) g: f64,
-    pub(crate) b: f64,
+pub struct DiffuseColour {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
 }
 /// A ray containing an origin and a direction in 3D space
-pub(crate) struct Ray {
-    pub(crate) origin: Vec3D,
-    pub(crate) direction: Vec3D
+pub struct Ray {
+    pub origin: Vec3D,
+    pub direction: Vec3D
+}
+/// # A direction towards a light source chosen by SceneObject::sample_light
+/// `pdf` is the solid angle density of `direction` as seen from the point the light was sampled for
+pub struct LightSample {
+    pub direction: Vec3D,
+    pub pdf: f64,
 }
-/// Intersection container containing the distance to the intersection and a reference to the object implementing the SceneObject trait
-pub(crate) struct Intersection<'a> {
-    pub(crate) distance: f64,
-    pub(crate) object: &'a Box<dyn SceneObject>
+/// Intersection container containing the distance to the intersection and a reference to the object implementing the SceneObject trait,
+/// together with its index in the scene's objects_list
+pub struct Intersection<'a> {
+    pub distance: f64,
+    pub index: usize,
+    pub object: &'a dyn SceneObject
 }
 /// # The scene containing objects to be rendered
-/// Contains a Vec<dyn Box> in which the Box type is a generic type for all structs implementing SceneObject
-pub(crate) struct RenderScene {
-    pub(crate) objects_list: Vec<Box<dyn SceneObject>>
+/// Contains a `Vec<Box<dyn SceneObject>>` in which the Box type is a generic type for all structs implementing SceneObject.
+/// Once the BVH has been built, by build_bvh or by a render with `use_bvh` set, closest-hit queries on bounded objects
+/// go through it, unbounded objects (planes) are always tested directly. The camera the scene is viewed through is
+/// part of the scene.
+/// `lights` indexes the emissive objects that can be sampled directly for next-event estimation
+pub struct RenderScene {
+    pub objects_list: Vec<Box<dyn SceneObject>>,
+    pub camera: Camera,
+    pub lights: Vec<usize>,
+    bvh: OnceLock<SceneBvh>,
+}
+
+/// # The BVH over a scene's bounded objects, with the indices of the objects it cannot hold
+struct SceneBvh {
+    bvh: Bvh,
+    unbounded_objects: Vec<usize>,
+}
+
+/// # An axis-aligned bounding box given by its minimum and maximum corners
+#[derive(Copy, Clone)]
+pub struct BoundingBox {
+    pub min: Vec3D,
+    pub max: Vec3D,
 }
 
 ///# A 3-dimensional vector with custom-implemented behaviour
 #[derive(Copy, Clone)]
-pub(crate) struct Vec3D {
-    pub(crate) x: f64,
-    pub(crate) y: f64,
-    pub(crate) z: f64,
+pub struct Vec3D {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
 }
 
 /// # A sphere for use in creating the 3-dimensional scene
-pub(crate) struct Sphere {
-    pub(crate) center: Vec3D,
-    pub(crate) radius: f64,
-    pub(crate) colour: DiffuseColour,
-    pub(crate) material_type: u8,
-    pub(crate) emission: f64,
-    pub(crate) refractive_index: f64,
+pub struct Sphere {
+    pub center: Vec3D,
+    pub radius: f64,
+    pub material: Material,
 }
 
 /// # A plane for use in creating the 3-dimensional scene
-pub(crate) struct Plane {
-    pub(crate) normal: Vec3D, // must be normalized using normalize_plane function
-    pub(crate) distance_to_origin: f64,
-    pub(crate) colour: DiffuseColour,
-    pub(crate) material_type: u8,
-    pub(crate) emission: f64,
-    pub(crate) refractive_index: f64,
+pub struct Plane {
+    pub normal: Vec3D, // must be of unit length, see normalize_plane
+    pub distance_to_origin: f64,
+    pub material: Material,
+}
+
+/// # A box with faces parallel to the coordinate planes, for use in creating the 3-dimensional scene
+/// Spans from the corner `min` to the corner `max`, which must be larger along every axis
+pub struct AxisAlignedBox {
+    pub min: Vec3D,
+    pub max: Vec3D,
+    pub material: Material,
+}
+
+/// # A parallelogram for use in creating the 3-dimensional scene
+/// Its corners are `corner`, `corner + edge1`, `corner + edge1 + edge2` and `corner + edge2`, the edges must not be
+/// parallel. Emissive quads are sampled uniformly by area, so they make area lights such as Cornell box ceiling panels
+pub struct Quad {
+    pub corner: Vec3D,
+    pub edge1: Vec3D,
+    pub edge2: Vec3D,
+    pub material: Material,
+}
+
+/// # A flat disk for use in creating the 3-dimensional scene
+/// `normal` must be of unit length. Emissive disks are sampled uniformly by area, so they make round area lights
+pub struct Disk {
+    pub center: Vec3D,
+    pub normal: Vec3D,
+    pub radius: f64,
+    pub material: Material,
 }
 
+/// # A single triangle for use in creating the 3-dimensional scene
+/// Vertex normals and texture coordinates are optional and interpolated across the face when given,
+/// otherwise the geometric normal is used (counter-clockwise winding faces the viewer)
+pub struct Triangle {
+    pub vertices: [Vec3D; 3],
+    pub normals: Option<[Vec3D; 3]>,
+    pub uvs: Option<[[f64; 2]; 3]>,
+    pub material: Material,
+}
+
+/// # An indexed triangle mesh sharing one material
+/// Every entry of `indices` names the three vertices of a face. `normals` and `uvs` are either empty or hold one
+/// entry per position. The geometry is fixed once built, as the mesh keeps its own BVH over the faces
+pub struct TriangleMesh {
+    positions: Vec<Vec3D>,
+    normals: Vec<Vec3D>,
+    uvs: Vec<[f64; 2]>,
+    indices: Vec<[usize; 3]>,
+    pub material: Material,
+    bvh: Bvh,
+}
+
+/// # Why a TriangleMesh could not be built from the given geometry
+#[derive(Debug)]
+pub enum MeshError {
+    /// The mesh has no faces
+    NoFaces,
+    /// A face refers to a position past the end of the positions
+    MissingPosition { index: usize, positions: usize },
+    /// Normals or uvs were given, but not one for each position
+    CountMismatch { field: &'static str, expected: usize, found: usize },
+    /// A position, normal or uv is NaN or infinite
+    NonFinite { field: &'static str, index: usize },
+    /// A vertex normal has zero length, so it points nowhere
+    ZeroNormal { index: usize },
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::NoFaces => write!(f, "mesh has no faces"),
+            MeshError::MissingPosition { index, positions } =>
+                write!(f, "mesh index {index} refers to a position that does not exist, there are {positions}"),
+            MeshError::CountMismatch { field, expected, found } =>
+                write!(f, "mesh must have one entry in {field} per position or none, expected {expected}, found {found}"),
+            MeshError::NonFinite { field, index } => write!(f, "mesh {field}[{index}] is NaN or infinite"),
+            MeshError::ZeroNormal { index } => write!(f, "mesh normals[{index}] has zero length"),
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
 // ***shared traits
-pub(crate) trait SceneObject {
+/// Objects are shared between the render threads, so they must be Send + Sync
+pub trait SceneObject: Send + Sync {
     /// Computes the surface normal of the object at a given point, returns a reference
     fn normal(&self, intersect_point: &Vec3D) -> Vec3D;
     /// Computes the intersection distance of the object with a given ray
     fn intersect(&self, intersect_ray: &Ray) -> f64;
-    fn colour(&self) -> DiffuseColour;
-    fn emission(&self) -> f64;
-    fn material_type(&self) -> u8;
-    fn refractive_index(&self) -> f64;
+    /// The material the object's surface is made of
+    fn material(&self) -> &Material;
+    /// Axis-aligned box enclosing the object, None for unbounded objects such as planes
+    fn bounding_box(&self) -> Option<BoundingBox>;
+    /// Computes the shading normal where the given ray hits the object at the given distance.
+    /// Objects made of several faces override this to find the face that was hit
+    fn surface_normal(&self, intersect_ray: &Ray, distance: f64) -> Vec3D {
+        self.normal(&intersect_ray.origin.add(&intersect_ray.direction.scalar_mult(distance)))
+    }
+    /// Whether sample_light is implemented, only such emissive objects are sampled directly as lights
+    fn supports_light_sampling(&self) -> bool {
+        false
+    }
+    /// Choose a direction from `reference` towards a point on the object, `random` is a point of the unit square
+    fn sample_light(&self, _reference: &Vec3D, _random: (f64, f64)) -> Option<LightSample> {
+        None
+    }
+    /// Solid angle density with which sample_light chooses `direction` from `reference`, 0 if it never does
+    fn light_pdf(&self, _reference: &Vec3D, _direction: &Vec3D) -> f64 {
+        0.0
+    }
+    /// Check that the object's parameters can be rendered, Err says what is wrong. RenderScene::new refuses objects
+    /// that fail this; by default only the material is checked
+    fn check(&self) -> Result<(), String> {
+        self.material().check()
+    }
+    /// Problems with the object's shape for RenderScene::validate that check lets through, such as degenerate faces
+    fn validate_geometry(&self) -> Vec<(Severity, String)> {
+        Vec::new()
+    }
 }
 impl SceneObject for Sphere {
     fn normal(&self, intersect_point: &Vec3D) -> Vec3D {
-        self.center.scalar_mult(-1.0).add(intersect_point).scalar_mult(1.0/self.radius)
+        intersect_point.subtract(&self.center).scalar_mult(1.0/self.radius)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if !self.center.is_finite() || !self.radius.is_finite() {
+            return Err("sphere center or radius is NaN or infinite".to_string());
+        }
+        if self.radius <= 0.0 {
+            return Err(format!("sphere radius {} must be greater than zero", self.radius));
+        }
+        self.material.check()
     }
     /// compute the intersection distance of the ray and the sphere
     fn intersect(&self, intersect_ray: &Ray) -> f64 {
@@ -91,34 +240,97 @@ impl SceneObject for Sphere {
         return_type
     }
 
-    fn colour(&self) -> DiffuseColour {
-        self.colour.clone()
+    fn material(&self) -> &Material {
+        &self.material
     }
 
-    fn emission(&self) -> f64 {
-        self.emission
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let extent = Vec3D {x: self.radius, y: self.radius, z: self.radius};
+        Some(BoundingBox {
+            min: self.center.subtract(&extent),
+            max: self.center.add(&extent),
+        })
     }
 
-    fn material_type(&self) -> u8 {
-        self.material_type
+    fn supports_light_sampling(&self) -> bool {
+        true
+    }
+
+    /// Uniformly samples the cone of directions the sphere subtends as seen from outside,
+    /// from inside the sphere a uniformly chosen point of the surface is used instead
+    fn sample_light(&self, reference: &Vec3D, random: (f64, f64)) -> Option<LightSample> {
+        let mut to_center = self.center.subtract(reference);
+        let distance_squared = to_center.dot(&to_center);
+        let radius_squared = self.radius*self.radius;
+        let angle = 2.0*PI*random.1;
+
+        if distance_squared <= radius_squared {
+            let z = 1.0 - 2.0*random.0;
+            let ring = (1.0 - z*z).max(0.0).sqrt();
+            let surface_normal = Vec3D {x: ring*angle.cos(), y: ring*angle.sin(), z};
+            let mut direction = self.center.add(&surface_normal.scalar_mult(self.radius)).subtract(reference);
+            let distance = direction.length();
+            direction.norm();
+            let cosine = surface_normal.dot(&direction).abs();
+            if distance == 0.0 || cosine == 0.0 {
+                return None;
+            }
+            // convert the area density 1 / (4 pi r^2) to solid angle
+            return Some(LightSample {
+                direction,
+                pdf: distance*distance/(cosine*4.0*PI*radius_squared),
+            });
+        }
+
+        let cosine_max = (1.0 - radius_squared/distance_squared).max(0.0).sqrt();
+        let cosine = 1.0 - random.0*(1.0 - cosine_max);
+        let sine = (1.0 - cosine*cosine).max(0.0).sqrt();
+        to_center.norm();
+        let mut rotation_x = Vec3D {x: 0.0, y: 0.0, z: 0.0};
+        let mut rotation_y = Vec3D {x: 0.0, y: 0.0, z: 0.0};
+        to_center.orthonormal_system(&mut rotation_x, &mut rotation_y);
+        let mut direction = rotation_x.scalar_mult(sine*angle.cos())
+            .add(&rotation_y.scalar_mult(sine*angle.sin()))
+            .add(&to_center.scalar_mult(cosine));
+        direction.norm();
+        Some(LightSample {
+            direction,
+            pdf: 1.0/(2.0*PI*(1.0 - cosine_max)),
+        })
     }
 
-    fn refractive_index(&self) -> f64 {
-        self.refractive_index
+    fn light_pdf(&self, reference: &Vec3D, direction: &Vec3D) -> f64 {
+        let distance = self.intersect(&Ray {origin: *reference, direction: *direction});
+        if distance <= 0.0 {
+            return 0.0;
+        }
+        let to_center = self.center.subtract(reference);
+        let distance_squared = to_center.dot(&to_center);
+        let radius_squared = self.radius*self.radius;
+        if distance_squared <= radius_squared {
+            let surface_normal = self.normal(&reference.add(&direction.scalar_mult(distance)));
+            let cosine = surface_normal.dot(direction).abs();
+            if cosine == 0.0 {
+                return 0.0;
+            }
+            return distance*distance/(cosine*4.0*PI*radius_squared);
+        }
+        let cosine_max = (1.0 - radius_squared/distance_squared).max(0.0).sqrt();
+        1.0/(2.0*PI*(1.0 - cosine_max))
     }
 }
 
 impl SceneObject for Plane {
     /// return reference to the normalized normal vector (Vec3D) of the plane
     fn normal(&self, _intersect_point: &Vec3D) -> Vec3D {
-        self.normal.clone()
+        self.normal
     }
 
     /// compute intersection distance of the plane and the given ray
     fn intersect(&self, intersect_ray: &Ray) -> f64 {
         let intersect_direction_component = self.normal.dot(&intersect_ray.direction);
         if intersect_direction_component != 0.0 {
-            let temp_result = -1.0 * (self.normal.dot(&intersect_ray.origin)+self.distance_to_origin)/intersect_direction_component;
+            let temp_result = -(self.normal.dot(&intersect_ray.origin)+self.distance_to_origin)/intersect_direction_component;
             if temp_result > EPSILON {
                 temp_result
             }else {
@@ -129,20 +341,332 @@ impl SceneObject for Plane {
         }
     }
 
-    fn colour(&self) -> DiffuseColour {
-        self.colour.clone()
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    /// planes are infinite and have no bounding box
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        None
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if !self.normal.is_finite() || !self.distance_to_origin.is_finite() {
+            return Err("plane normal or distance_to_origin is NaN or infinite".to_string());
+        }
+        let length = self.normal.length();
+        if (length - 1.0).abs() > 1e-6 {
+            return Err(format!("plane normal has length {length} instead of 1, normalize it with normalize_plane"));
+        }
+        self.material.check()
+    }
+}
+
+impl SceneObject for AxisAlignedBox {
+    /// outward normal of the face closest to the point
+    fn normal(&self, intersect_point: &Vec3D) -> Vec3D {
+        let faces = [
+            (intersect_point.x - self.min.x, Vec3D {x: -1.0, y: 0.0, z: 0.0}),
+            (self.max.x - intersect_point.x, Vec3D {x: 1.0, y: 0.0, z: 0.0}),
+            (intersect_point.y - self.min.y, Vec3D {x: 0.0, y: -1.0, z: 0.0}),
+            (self.max.y - intersect_point.y, Vec3D {x: 0.0, y: 1.0, z: 0.0}),
+            (intersect_point.z - self.min.z, Vec3D {x: 0.0, y: 0.0, z: -1.0}),
+            (self.max.z - intersect_point.z, Vec3D {x: 0.0, y: 0.0, z: 1.0}),
+        ];
+        let mut closest = faces[0];
+        for face in &faces[1..] {
+            if face.0.abs() < closest.0.abs() {
+                closest = *face;
+            }
+        }
+        closest.1
+    }
+
+    /// slab intersection: the ray is inside the box where it is between the two planes of every axis at once.
+    /// From outside the entry distance is returned, from inside the exit distance
+    fn intersect(&self, intersect_ray: &Ray) -> f64 {
+        let mut near = f64::NEG_INFINITY;
+        let mut far = f64::INFINITY;
+        for (origin, direction, min, max) in [
+            (intersect_ray.origin.x, intersect_ray.direction.x, self.min.x, self.max.x),
+            (intersect_ray.origin.y, intersect_ray.direction.y, self.min.y, self.max.y),
+            (intersect_ray.origin.z, intersect_ray.direction.z, self.min.z, self.max.z),
+        ] {
+            let inverse = 1.0/direction;
+            let t1 = (min - origin)*inverse;
+            let t2 = (max - origin)*inverse;
+            // f64::min/max ignore the NaN produced by 0*inf when the ray lies in a slab plane
+            near = near.max(t1.min(t2));
+            far = far.min(t1.max(t2));
+        }
+        if near > far {
+            0.0
+        }else if near > EPSILON {
+            near
+        }else if far > EPSILON {
+            far
+        }else {
+            0.0
+        }
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        Some(BoundingBox {
+            min: self.min,
+            max: self.max,
+        })
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if !self.min.is_finite() || !self.max.is_finite() {
+            return Err("box min or max is NaN or infinite".to_string());
+        }
+        if self.min.x >= self.max.x || self.min.y >= self.max.y || self.min.z >= self.max.z {
+            return Err("box min must be smaller than max along every axis".to_string());
+        }
+        self.material.check()
+    }
+}
+
+impl SceneObject for Quad {
+    fn normal(&self, _intersect_point: &Vec3D) -> Vec3D {
+        self.unit_normal()
+    }
+
+    /// intersect the plane of the quad, then keep the hit if its coordinates along both edges lie within [0, 1]
+    fn intersect(&self, intersect_ray: &Ray) -> f64 {
+        let normal = self.edge1.cross(&self.edge2);
+        let denominator = normal.dot(&intersect_ray.direction);
+        if denominator == 0.0 {
+            return 0.0;
+        }
+        let distance = normal.dot(&self.corner.subtract(&intersect_ray.origin))/denominator;
+        if distance <= EPSILON {
+            return 0.0;
+        }
+        let offset = intersect_ray.origin.add(&intersect_ray.direction.scalar_mult(distance)).subtract(&self.corner);
+        let scaled_normal = normal.scalar_div(normal.dot(&normal));
+        let along_edge1 = scaled_normal.dot(&offset.cross(&self.edge2));
+        let along_edge2 = scaled_normal.dot(&self.edge1.cross(&offset));
+        if (0.0..=1.0).contains(&along_edge1) && (0.0..=1.0).contains(&along_edge2) {
+            distance
+        }else {
+            0.0
+        }
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
     }
 
-    fn emission(&self) -> f64 {
-        self.emission
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let opposite = self.corner.add(&self.edge1).add(&self.edge2);
+        Some(BoundingBox::from_point(self.corner)
+            .union(&BoundingBox::from_point(self.corner.add(&self.edge1)))
+            .union(&BoundingBox::from_point(self.corner.add(&self.edge2)))
+            .union(&BoundingBox::from_point(opposite)))
     }
 
-    fn material_type(&self) -> u8 {
-        self.material_type
+    fn supports_light_sampling(&self) -> bool {
+        true
     }
 
-    fn refractive_index(&self) -> f64 {
-        self.refractive_index
+    fn sample_light(&self, reference: &Vec3D, random: (f64, f64)) -> Option<LightSample> {
+        area_light_sample(reference, &self.sample_point(random), &self.unit_normal(), self.area())
+    }
+
+    fn light_pdf(&self, reference: &Vec3D, direction: &Vec3D) -> f64 {
+        area_light_pdf(self, reference, direction, &self.unit_normal(), self.area())
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if !self.corner.is_finite() || !self.edge1.is_finite() || !self.edge2.is_finite() {
+            return Err("quad corner or edges are NaN or infinite".to_string());
+        }
+        let area = self.area();
+        if !(area > 0.0 && area.is_finite()) {
+            return Err("quad edges must not be parallel or zero".to_string());
+        }
+        self.material.check()
+    }
+}
+
+impl SceneObject for Disk {
+    fn normal(&self, _intersect_point: &Vec3D) -> Vec3D {
+        self.normal
+    }
+
+    /// intersect the plane of the disk, then keep the hit if it lies within the radius of the center
+    fn intersect(&self, intersect_ray: &Ray) -> f64 {
+        let denominator = self.normal.dot(&intersect_ray.direction);
+        if denominator == 0.0 {
+            return 0.0;
+        }
+        let distance = self.normal.dot(&self.center.subtract(&intersect_ray.origin))/denominator;
+        if distance <= EPSILON {
+            return 0.0;
+        }
+        let offset = intersect_ray.origin.add(&intersect_ray.direction.scalar_mult(distance)).subtract(&self.center);
+        if offset.dot(&offset) <= self.radius*self.radius {
+            distance
+        }else {
+            0.0
+        }
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    /// along every axis the disk reaches radius times the sine of the angle between the axis and the normal
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let reach = |component: f64| self.radius*(1.0 - component*component).max(0.0).sqrt();
+        let extent = Vec3D {x: reach(self.normal.x), y: reach(self.normal.y), z: reach(self.normal.z)};
+        Some(BoundingBox {
+            min: self.center.subtract(&extent),
+            max: self.center.add(&extent),
+        })
+    }
+
+    fn supports_light_sampling(&self) -> bool {
+        true
+    }
+
+    fn sample_light(&self, reference: &Vec3D, random: (f64, f64)) -> Option<LightSample> {
+        area_light_sample(reference, &self.sample_point(random), &self.normal, self.area())
+    }
+
+    fn light_pdf(&self, reference: &Vec3D, direction: &Vec3D) -> f64 {
+        area_light_pdf(self, reference, direction, &self.normal, self.area())
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if !self.center.is_finite() || !self.normal.is_finite() || !self.radius.is_finite() {
+            return Err("disk center, normal or radius is NaN or infinite".to_string());
+        }
+        if self.radius <= 0.0 {
+            return Err(format!("disk radius {} must be greater than zero", self.radius));
+        }
+        let length = self.normal.length();
+        if (length - 1.0).abs() > 1e-6 {
+            return Err(format!("disk normal has length {length} instead of 1"));
+        }
+        self.material.check()
+    }
+}
+
+impl SceneObject for Triangle {
+    /// interpolated vertex normal if the triangle has them, the geometric normal otherwise
+    fn normal(&self, intersect_point: &Vec3D) -> Vec3D {
+        match &self.normals {
+            Some(normals) => {
+                let (u, v) = barycentric(&self.vertices, intersect_point);
+                interpolate_normal(normals, u, v)
+            }
+            None => geometric_normal(&self.vertices),
+        }
+    }
+
+    fn intersect(&self, intersect_ray: &Ray) -> f64 {
+        moller_trumbore(&self.vertices, intersect_ray).map_or(0.0, |(distance, _, _)| distance)
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        Some(BoundingBox::from_point(self.vertices[0])
+            .union(&BoundingBox::from_point(self.vertices[1]))
+            .union(&BoundingBox::from_point(self.vertices[2])))
+    }
+
+    fn surface_normal(&self, intersect_ray: &Ray, distance: f64) -> Vec3D {
+        match (&self.normals, moller_trumbore(&self.vertices, intersect_ray)) {
+            (Some(normals), Some((_, u, v))) => interpolate_normal(normals, u, v),
+            _ => self.normal(&intersect_ray.origin.add(&intersect_ray.direction.scalar_mult(distance))),
+        }
+    }
+
+    fn check(&self) -> Result<(), String> {
+        let normals = self.normals.iter().flatten();
+        let uvs = self.uvs.iter().flatten().flatten();
+        if !self.vertices.iter().chain(normals.clone()).all(Vec3D::is_finite) || !uvs.clone().all(|uv| uv.is_finite()) {
+            return Err("triangle has NaN or infinite vertices, normals or uvs".to_string());
+        }
+        if face_area(&self.vertices) == 0.0 {
+            return Err("triangle vertices are collinear, it has no area".to_string());
+        }
+        if normals.clone().any(|normal| normal.length() == 0.0) {
+            return Err("triangle has a zero vertex normal".to_string());
+        }
+        self.material.check()
+    }
+}
+
+impl SceneObject for TriangleMesh {
+    /// normal of the face containing the point, found by testing every face.
+    /// Rendering goes through surface_normal instead, which uses the BVH
+    fn normal(&self, intersect_point: &Vec3D) -> Vec3D {
+        let mut closest_face = 0;
+        let mut closest_distance = INFINITY;
+        for (i, face) in self.indices.iter().enumerate() {
+            let vertices = self.face_vertices(face);
+            let normal = geometric_normal(&vertices);
+            let distance = normal.dot(&intersect_point.subtract(&vertices[0])).abs();
+            let (u, v) = barycentric(&vertices, intersect_point);
+            if distance < closest_distance && u >= -EPSILON && v >= -EPSILON && u + v <= 1.0 + EPSILON {
+                closest_distance = distance;
+                closest_face = i;
+            }
+        }
+        let (u, v) = barycentric(&self.face_vertices(&self.indices[closest_face]), intersect_point);
+        self.face_normal(closest_face, u, v)
+    }
+
+    fn intersect(&self, intersect_ray: &Ray) -> f64 {
+        self.closest_face(intersect_ray).map_or(0.0, |(_, distance, _, _)| distance)
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let first = BoundingBox::from_point(*self.positions.first()?);
+        Some(self.positions.iter().fold(first, |bounds, position| bounds.union(&BoundingBox::from_point(*position))))
+    }
+
+    fn surface_normal(&self, intersect_ray: &Ray, distance: f64) -> Vec3D {
+        match self.closest_face(intersect_ray) {
+            Some((face, _, u, v)) => self.face_normal(face, u, v),
+            None => self.normal(&intersect_ray.origin.add(&intersect_ray.direction.scalar_mult(distance))),
+        }
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if let Some(error) = self.shape_error() {
+            return Err(error.to_string());
+        }
+        self.material.check()
+    }
+
+    fn validate_geometry(&self) -> Vec<(Severity, String)> {
+        // the faces cannot be looked at if check refuses the mesh's shape
+        if self.shape_error().is_some() {
+            return Vec::new();
+        }
+        // meshes often contain a few slivers, they are never hit so only worth a warning
+        let degenerate = self.indices.iter().filter(|face| face_area(&self.face_vertices(face)) == 0.0).count();
+        if degenerate > 0 {
+            vec![(Severity::Warning, format!("{degenerate} of the mesh's {} faces have no area", self.indices.len()))]
+        }else {
+            Vec::new()
+        }
     }
 }
 
@@ -150,131 +674,126 @@ impl SceneObject for Plane {
 
 impl DiffuseColour {
     /// addition to colour object with another directly
-    pub(crate) fn add(&mut self, colour: DiffuseColour) {
-        self.r = self.r + colour.r;
-        self.g = self.g + colour.g;
-        self.b = self.b + colour.b;
+    pub fn add(&mut self, colour: DiffuseColour) {
+        self.r += colour.r;
+        self.g += colour.g;
+        self.b += colour.b;
     }
     /// addition to colour object with another directly
-    pub(crate) fn add_return(&mut self, colour: DiffuseColour) -> DiffuseColour {
-        let result = DiffuseColour {
+    pub fn add_return(&mut self, colour: DiffuseColour) -> DiffuseColour {
+        DiffuseColour {
             r: self.r + colour.r,
             g: self.g + colour.g,
             b: self.b + colour.b
-        };
-        result
+        }
     }
     /// multiplication to colour object with another directly
-    pub(crate) fn mult_colour(&mut self, colour: &DiffuseColour) {
-        self.r = self.r * colour.r;
-        self.g = self.g * colour.g;
-        self.b = self.b * colour.b;
+    pub fn mult_colour(&mut self, colour: &DiffuseColour) {
+        self.r *= colour.r;
+        self.g *= colour.g;
+        self.b *= colour.b;
     }
     /// multiplication to colour object with another
-    pub(crate) fn mult_colour_return(&mut self, colour: DiffuseColour) -> DiffuseColour {
-        let result = DiffuseColour {
+    pub fn mult_colour_return(&mut self, colour: DiffuseColour) -> DiffuseColour {
+        DiffuseColour {
             r: self.r * colour.r,
             g: self.g * colour.g,
             b: self.b * colour.b
-        };
-        result
+        }
     }
     /// multiplication to colour object with f64 directly
-    pub(crate) fn mult(&mut self, scalar: f64) {
-        self.r = self.r * scalar;
-        self.g = self.g * scalar;
-        self.b = self.b * scalar;
+    pub fn mult(&mut self, scalar: f64) {
+        self.r *= scalar;
+        self.g *= scalar;
+        self.b *= scalar;
     }/// multiplication to colour object with f64
-    pub(crate) fn mult_return(&mut self, scalar: f64) -> DiffuseColour{
-        let result = DiffuseColour {
+    pub fn mult_return(&mut self, scalar: f64) -> DiffuseColour{
+        DiffuseColour {
             r: self.r * scalar,
             g: self.g * scalar,
             b: self.b * scalar,
-        };
-        result
+        }
     }
 }
 
 impl Vec3D {
     /// Add a Vec3D to another and return the result
-    pub(crate) fn add(&self, other: &Vec3D) -> Vec3D {
-        let result = Vec3D {
+    pub fn add(&self, other: &Vec3D) -> Vec3D {
+        Vec3D {
             x: self.x + other.x,
             y: self.y + other.y,
             z: self.z + other.z,
-        };
-        result
+        }
     }
     /// Subtract a Vec3D from another and return the result
-    pub(crate) fn subtract(&self, other: &Vec3D) -> Vec3D {
-        let result = Vec3D {
+    pub fn subtract(&self, other: &Vec3D) -> Vec3D {
+        Vec3D {
             x: self.x - other.x,
             y: self.y - other.y,
             z: self.z - other.z,
-        };
-        result
+        }
     }
     /// Do a cross-product of 2 Vec3D and return the result
-    pub(crate) fn cross(&self, other: &Vec3D) -> Vec3D {
-        let result = Vec3D {
+    pub fn cross(&self, other: &Vec3D) -> Vec3D {
+        Vec3D {
             x: self.y*other.z - self.z*other.y,
             y: self.z*other.x - self.x*other.z,
             z: self.x*other.y - self.y*other.x,
-        };
-        result
+        }
     }
     /// Multiply the Vec3D by a scalar
-    pub(crate) fn scalar_mult(&self, scalar: f64) -> Vec3D {
-        let result = Vec3D {
+    pub fn scalar_mult(&self, scalar: f64) -> Vec3D {
+        Vec3D {
             x: self.x * scalar,
             y: self.y * scalar,
             z: self.z * scalar,
-        };
-        result
+        }
     }
     /// Divide the Vec3D by a scalar
-    pub(crate) fn scalar_div(&self, scalar: f64) -> Vec3D{
-        let result = Vec3D {
+    pub fn scalar_div(&self, scalar: f64) -> Vec3D{
+        Vec3D {
             x: self.x / scalar,
             y: self.y / scalar,
             z: self.z / scalar,
-        };
-        result
+        }
     }
     /// Perform a dot-product of two Vec3D and return the resulting scalar f64
-    pub(crate) fn dot(&self, other: &Vec3D) -> f64 {
+    pub fn dot(&self, other: &Vec3D) -> f64 {
         self.x*other.x + self.y*other.y + self.z*other.z
     }
     /// Compute the length of the Vec3D and return the scalar f64
-    pub(crate) fn length(&self) -> f64 {
+    pub fn length(&self) -> f64 {
         (self.x*self.x + self.y*self.y + self.z*self.z).sqrt()
     }
+    /// Whether no component is NaN or infinite
+    pub fn is_finite(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
+    }
     /// Normalize the current Vec3D - mutates self
     /// Return zero vector if the norm will result in division by 0
-    pub(crate) fn norm(&mut self) {
+    pub fn norm(&mut self) {
         let length = self.length();
         if length == 0.0 {
             self.x = 0.0;
             self.y = 0.0;
             self.z = 0.0;
         }else {
-            self.x = self.x/length;
-            self.y = self.y/length;
-            self.z = self.z/length;
+            self.x /= length;
+            self.y /= length;
+            self.z /= length;
         }
     }
     /// Perform Hadamard (element-wise) product of two Vec3D and return the result.
-    pub(crate) fn hadamard(&self, other: &Vec3D) -> Vec3D {
-        let result = Vec3D {
+    pub fn hadamard(&self, other: &Vec3D) -> Vec3D {
+        Vec3D {
             x: self.x * other.x,
             y: self.y * other.y,
             z: self.z * other.z,
-        };
-        result.clone()
+        }
     }
     /// Create an orthonormal system of 3 vectors Vec3D assuming self is normalized - mutates self, vec1, and vec 2
     /// This relies on vec 2 and vec 3 being empty but mutable. -> Done this way to preserve exterior scope of the two vectors
-    pub(crate) fn orthonormal_system(&self, vec2: &mut Vec3D, vec3: &mut Vec3D) {
+    pub fn orthonormal_system(&self, vec2: &mut Vec3D, vec3: &mut Vec3D) {
         if self.x.abs() > self.y.abs() {
             let target_length = 1.0_f64/((self.x*self.x+self.z*self.z).sqrt());
             vec2.x = -self.z* target_length;
@@ -292,7 +811,7 @@ impl Vec3D {
         vec3.z = self.x*vec2.y - self.y*vec2.x;
     }
     /// Print the elements of the Vec3D in order.
-    pub(crate) fn print(&self) {
+    pub fn print(&self) {
         println!("{}, {}, {}", self.x, self.y, self.z)
     }
 }
@@ -303,41 +822,426 @@ impl Sphere {
 
 impl Plane {
     /// use this the safely create a plane
-    pub(crate) fn normalize_plane(&mut self){
+    pub fn normalize_plane(&mut self){
         self.normal.norm();
     }
 }
 
-impl Ray {
-    pub(crate) fn set_direction(&mut self, direction: &Vec3D) {
-        self.direction.x = direction.x;
-        self.direction.x = direction.y;
-        self.direction.x = direction.z;
+impl Quad {
+    /// Normal in the direction of edge1 x edge2, scaled to unit length
+    pub fn unit_normal(&self) -> Vec3D {
+        let mut normal = self.edge1.cross(&self.edge2);
+        normal.norm();
+        normal
+    }
+
+    pub fn area(&self) -> f64 {
+        self.edge1.cross(&self.edge2).length()
+    }
+
+    /// Point of the quad for a point of the unit square, uniformly distributed over its area
+    pub fn sample_point(&self, random: (f64, f64)) -> Vec3D {
+        self.corner.add(&self.edge1.scalar_mult(random.0)).add(&self.edge2.scalar_mult(random.1))
+    }
+}
+
+impl Disk {
+    pub fn area(&self) -> f64 {
+        PI*self.radius*self.radius
+    }
+
+    /// Point of the disk for a point of the unit square, uniformly distributed over its area
+    pub fn sample_point(&self, random: (f64, f64)) -> Vec3D {
+        let (x, y) = concentric_disk(random.0, random.1);
+        let mut tangent = Vec3D {x: 0.0, y: 0.0, z: 0.0};
+        let mut bitangent = Vec3D {x: 0.0, y: 0.0, z: 0.0};
+        self.normal.orthonormal_system(&mut tangent, &mut bitangent);
+        self.center.add(&tangent.scalar_mult(x*self.radius)).add(&bitangent.scalar_mult(y*self.radius))
+    }
+}
+
+/// Direction from `reference` towards `point`, chosen uniformly on a flat light of the given normal and area.
+/// The area density 1 / area becomes the solid angle density distance^2 / (cosine * area)
+fn area_light_sample(reference: &Vec3D, point: &Vec3D, normal: &Vec3D, area: f64) -> Option<LightSample> {
+    let mut direction = point.subtract(reference);
+    let distance_squared = direction.dot(&direction);
+    direction.norm();
+    let cosine = normal.dot(&direction).abs();
+    if distance_squared == 0.0 || cosine == 0.0 {
+        return None;
+    }
+    Some(LightSample {
+        direction,
+        pdf: distance_squared/(cosine*area),
+    })
+}
+
+/// Solid angle density with which area_light_sample chooses `direction` from `reference` on `light`
+fn area_light_pdf(light: &dyn SceneObject, reference: &Vec3D, direction: &Vec3D, normal: &Vec3D, area: f64) -> f64 {
+    let distance = light.intersect(&Ray {origin: *reference, direction: *direction});
+    let cosine = normal.dot(direction).abs();
+    if distance <= 0.0 || cosine == 0.0 {
+        return 0.0;
+    }
+    distance*distance/(cosine*area)
+}
+
+/// Möller–Trumbore ray-triangle intersection.
+/// Returns the distance along the ray and the barycentric coordinates (u, v) of the hit, weighting vertices 1 and 2
+pub(crate) fn moller_trumbore(vertices: &[Vec3D; 3], intersect_ray: &Ray) -> Option<(f64, f64, f64)> {
+    let edge1 = vertices[1].subtract(&vertices[0]);
+    let edge2 = vertices[2].subtract(&vertices[0]);
+    let p = intersect_ray.direction.cross(&edge2);
+    let determinant = edge1.dot(&p);
+    // ray parallel to the triangle
+    if determinant.abs() < EPSILON*EPSILON {
+        return None;
+    }
+    let inverse_determinant = 1.0/determinant;
+    let origin_offset = intersect_ray.origin.subtract(&vertices[0]);
+    let u = origin_offset.dot(&p)*inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = origin_offset.cross(&edge1);
+    let v = intersect_ray.direction.dot(&q)*inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let distance = edge2.dot(&q)*inverse_determinant;
+    if distance > EPSILON {
+        Some((distance, u, v))
+    }else {
+        None
+    }
+}
+
+/// Barycentric coordinates (u, v) of a point in the plane of the triangle, weighting vertices 1 and 2
+pub(crate) fn barycentric(vertices: &[Vec3D; 3], point: &Vec3D) -> (f64, f64) {
+    let edge1 = vertices[1].subtract(&vertices[0]);
+    let edge2 = vertices[2].subtract(&vertices[0]);
+    let offset = point.subtract(&vertices[0]);
+    let d11 = edge1.dot(&edge1);
+    let d12 = edge1.dot(&edge2);
+    let d22 = edge2.dot(&edge2);
+    let d1p = edge1.dot(&offset);
+    let d2p = edge2.dot(&offset);
+    let denominator = d11*d22 - d12*d12;
+    if denominator == 0.0 {
+        return (0.0, 0.0);
+    }
+    ((d22*d1p - d12*d2p)/denominator, (d11*d2p - d12*d1p)/denominator)
+}
+
+/// Area of the triangle spanned by three vertices
+fn face_area(vertices: &[Vec3D; 3]) -> f64 {
+    vertices[1].subtract(&vertices[0]).cross(&vertices[2].subtract(&vertices[0])).length()/2.0
+}
+
+/// Normalized normal of the triangle's plane, following counter-clockwise winding
+fn geometric_normal(vertices: &[Vec3D; 3]) -> Vec3D {
+    let mut normal = vertices[1].subtract(&vertices[0]).cross(&vertices[2].subtract(&vertices[0]));
+    normal.norm();
+    normal
+}
+
+/// Blend three vertex normals with barycentric coordinates and normalize the result
+fn interpolate_normal(normals: &[Vec3D; 3], u: f64, v: f64) -> Vec3D {
+    let mut normal = normals[0].scalar_mult(1.0 - u - v)
+        .add(&normals[1].scalar_mult(u))
+        .add(&normals[2].scalar_mult(v));
+    normal.norm();
+    normal
+}
+
+/// Blend three texture coordinates with barycentric coordinates
+fn interpolate_uv(uvs: &[[f64; 2]; 3], u: f64, v: f64) -> [f64; 2] {
+    let w = 1.0 - u - v;
+    [
+        uvs[0][0]*w + uvs[1][0]*u + uvs[2][0]*v,
+        uvs[0][1]*w + uvs[1][1]*u + uvs[2][1]*v,
+    ]
+}
+
+impl Triangle {
+    /// Texture coordinates at a point on the triangle, None if the triangle has none
+    pub fn uv(&self, intersect_point: &Vec3D) -> Option<[f64; 2]> {
+        let uvs = self.uvs.as_ref()?;
+        let (u, v) = barycentric(&self.vertices, intersect_point);
+        Some(interpolate_uv(uvs, u, v))
+    }
+}
+
+/// The first problem with a mesh's faces and positions, checked before any face is looked at
+fn faces_error(positions: &[Vec3D], indices: &[[usize; 3]]) -> Option<MeshError> {
+    if indices.is_empty() {
+        return Some(MeshError::NoFaces);
+    }
+    if let Some(index) = positions.iter().position(|position| !position.is_finite()) {
+        return Some(MeshError::NonFinite { field: "positions", index });
+    }
+    let index = indices.iter().flatten().find(|index| **index >= positions.len())?;
+    Some(MeshError::MissingPosition { index: *index, positions: positions.len() })
+}
+
+/// The first problem with a mesh's vertex normals, an empty list means the mesh has none
+fn normals_error(normals: &[Vec3D], positions: usize) -> Option<MeshError> {
+    if !normals.is_empty() && normals.len() != positions {
+        return Some(MeshError::CountMismatch { field: "normals", expected: positions, found: normals.len() });
+    }
+    if let Some(index) = normals.iter().position(|normal| !normal.is_finite()) {
+        return Some(MeshError::NonFinite { field: "normals", index });
+    }
+    let index = normals.iter().position(|normal| normal.length() == 0.0)?;
+    Some(MeshError::ZeroNormal { index })
+}
 
+/// The first problem with a mesh's texture coordinates, an empty list means the mesh has none
+fn uvs_error(uvs: &[[f64; 2]], positions: usize) -> Option<MeshError> {
+    if !uvs.is_empty() && uvs.len() != positions {
+        return Some(MeshError::CountMismatch { field: "uvs", expected: positions, found: uvs.len() });
+    }
+    let index = uvs.iter().position(|uv| !uv.iter().all(|value| value.is_finite()))?;
+    Some(MeshError::NonFinite { field: "uvs", index })
+}
+
+impl TriangleMesh {
+    /// Create a mesh from vertex positions and faces, building the BVH over its faces.
+    /// Vertex normals and texture coordinates can be added with with_normals and with_uvs.
+    /// Fails if there are no faces, a position is not finite or a face refers to a position that does not exist
+    pub fn new(positions: Vec<Vec3D>, indices: Vec<[usize; 3]>, material: Material) -> Result<TriangleMesh, MeshError> {
+        if let Some(error) = faces_error(&positions, &indices) {
+            return Err(error);
+        }
+        let mut mesh = TriangleMesh {
+            positions,
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices,
+            material,
+            bvh: Bvh::build(Vec::new()),
+        };
+        let faces = mesh.indices.iter().enumerate().map(|(i, face)| {
+            let vertices = mesh.face_vertices(face);
+            (i, BoundingBox::from_point(vertices[0])
+                .union(&BoundingBox::from_point(vertices[1]))
+                .union(&BoundingBox::from_point(vertices[2])))
+        }).collect();
+        mesh.bvh = Bvh::build(faces);
+        Ok(mesh)
+    }
+
+    /// Set per-vertex normals, one finite, non-zero normal for each position
+    pub fn with_normals(mut self, normals: Vec<Vec3D>) -> Result<TriangleMesh, MeshError> {
+        if let Some(error) = normals_error(&normals, self.positions.len()) {
+            return Err(error);
+        }
+        self.normals = normals;
+        Ok(self)
+    }
+
+    /// Set per-vertex texture coordinates, one for each position
+    pub fn with_uvs(mut self, uvs: Vec<[f64; 2]>) -> Result<TriangleMesh, MeshError> {
+        if let Some(error) = uvs_error(&uvs, self.positions.len()) {
+            return Err(error);
+        }
+        self.uvs = uvs;
+        Ok(self)
+    }
+
+    /// Vertex positions
+    pub fn positions(&self) -> &[Vec3D] {
+        &self.positions
+    }
+
+    /// Per-vertex normals, empty if the mesh is shaded with its geometric normals
+    pub fn normals(&self) -> &[Vec3D] {
+        &self.normals
+    }
+
+    /// Per-vertex texture coordinates, empty if the mesh has none
+    pub fn uvs(&self) -> &[[f64; 2]] {
+        &self.uvs
+    }
+
+    /// The faces, each naming three positions
+    pub fn indices(&self) -> &[[usize; 3]] {
+        &self.indices
+    }
+
+    /// What makes the mesh's shape unusable, checked before any of its faces are looked at
+    fn shape_error(&self) -> Option<MeshError> {
+        faces_error(&self.positions, &self.indices)
+            .or_else(|| normals_error(&self.normals, self.positions.len()))
+            .or_else(|| uvs_error(&self.uvs, self.positions.len()))
+    }
+
+    fn face_vertices(&self, face: &[usize; 3]) -> [Vec3D; 3] {
+        [self.positions[face[0]], self.positions[face[1]], self.positions[face[2]]]
+    }
+
+    /// Closest face hit by the ray as (face index, distance, u, v)
+    fn closest_face(&self, intersect_ray: &Ray) -> Option<(usize, f64, f64, f64)> {
+        let (face, _) = self.bvh.closest_hit(intersect_ray, |i| self.intersect_face(i, intersect_ray).map_or(0.0, |(distance, _, _)| distance))?;
+        let (distance, u, v) = self.intersect_face(face, intersect_ray)?;
+        Some((face, distance, u, v))
+    }
+
+    fn intersect_face(&self, face: usize, intersect_ray: &Ray) -> Option<(f64, f64, f64)> {
+        moller_trumbore(&self.face_vertices(&self.indices[face]), intersect_ray)
+    }
+
+    /// Shading normal of a face at barycentric coordinates (u, v)
+    fn face_normal(&self, face: usize, u: f64, v: f64) -> Vec3D {
+        let indices = self.indices[face];
+        if self.normals.is_empty() {
+            geometric_normal(&self.face_vertices(&indices))
+        }else {
+            interpolate_normal(&[self.normals[indices[0]], self.normals[indices[1]], self.normals[indices[2]]], u, v)
+        }
+    }
+
+    /// Texture coordinates where the ray hits the mesh, None on a miss or if the mesh has none
+    pub fn uv(&self, intersect_ray: &Ray) -> Option<[f64; 2]> {
+        if self.uvs.is_empty() {
+            return None;
+        }
+        let (face, _, u, v) = self.closest_face(intersect_ray)?;
+        let indices = self.indices[face];
+        Some(interpolate_uv(&[self.uvs[indices[0]], self.uvs[indices[1]], self.uvs[indices[2]]], u, v))
+    }
+}
+
+impl BoundingBox {
+    /// A box containing just one point
+    pub fn from_point(point: Vec3D) -> BoundingBox {
+        BoundingBox { min: point, max: point }
+    }
+    /// Smallest box containing both boxes
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        BoundingBox {
+            min: Vec3D {x: self.min.x.min(other.min.x), y: self.min.y.min(other.min.y), z: self.min.z.min(other.min.z)},
+            max: Vec3D {x: self.max.x.max(other.max.x), y: self.max.y.max(other.max.y), z: self.max.z.max(other.max.z)},
+        }
+    }
+    /// Center point of the box
+    pub fn centroid(&self) -> Vec3D {
+        self.min.add(&self.max).scalar_mult(0.5)
+    }
+    /// Surface area of the box, used by the surface area heuristic
+    pub fn surface_area(&self) -> f64 {
+        let extent = self.max.subtract(&self.min);
+        2.0*(extent.x*extent.y + extent.y*extent.z + extent.z*extent.x)
+    }
+    /// Slab test: does the ray enter the box before max_distance.
+    /// inverse_direction holds 1/direction per axis and is precomputed once per ray by the caller
+    pub fn hit(&self, ray: &Ray, inverse_direction: &[f64; 3], max_distance: f64) -> bool {
+        let mut near = 0.0_f64;
+        let mut far = max_distance;
+        for (origin, inverse, min, max) in [
+            (ray.origin.x, inverse_direction[0], self.min.x, self.max.x),
+            (ray.origin.y, inverse_direction[1], self.min.y, self.max.y),
+            (ray.origin.z, inverse_direction[2], self.min.z, self.max.z),
+        ] {
+            let t1 = (min - origin)*inverse;
+            let t2 = (max - origin)*inverse;
+            // f64::min/max ignore the NaN produced by 0*inf when the ray lies in a slab plane
+            near = near.max(t1.min(t2));
+            far = far.min(t1.max(t2));
+        }
+        near <= far
     }
 }
 
 impl RenderScene {
-    /// Get the closest intersection, returns in an Option<> in case of no intersection
-    pub(crate) fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+    /// Create a scene from a list of objects, with the default camera and without an acceleration structure.
+    /// Fails with the index of the first object whose parameters cannot be rendered, see SceneObject::check
+    pub fn new(objects_list: Vec<Box<dyn SceneObject>>) -> Result<RenderScene, Error> {
+        for (index, object) in objects_list.iter().enumerate() {
+            object.check().map_err(|message| Error::InvalidObject { index, message })?;
+        }
+        let mut scene = RenderScene {
+            objects_list,
+            camera: Camera::default(),
+            lights: Vec::new(),
+            bvh: OnceLock::new(),
+        };
+        scene.collect_lights();
+        Ok(scene)
+    }
+
+    /// Find the emissive objects that can be sampled as lights. Must be called again after changing objects_list
+    pub fn collect_lights(&mut self) {
+        self.lights = self.objects_list.iter().enumerate()
+            .filter(|(_, obj)| {
+                let emitted = obj.material().emitted();
+                obj.supports_light_sampling() && emitted.r + emitted.g + emitted.b > 0.0
+            })
+            .map(|(i, _)| i)
+            .collect();
+    }
+
+    /// Build the bounding volume hierarchy used by intersect. Must be called again after changing objects_list
+    pub fn build_bvh(&mut self) {
+        self.bvh = OnceLock::from(self.scene_bvh());
+    }
+
+    /// Build the bounding volume hierarchy unless the scene already has one
+    pub(crate) fn ensure_bvh(&self) {
+        self.bvh.get_or_init(|| self.scene_bvh());
+    }
+
+    fn scene_bvh(&self) -> SceneBvh {
+        let mut bounded = Vec::new();
+        let mut unbounded_objects = Vec::new();
+        for (i, obj) in self.objects_list.iter().enumerate() {
+            match obj.bounding_box() {
+                Some(bounds) => bounded.push((i, bounds)),
+                None => unbounded_objects.push(i),
+            }
+        }
+        SceneBvh { bvh: Bvh::build(bounded), unbounded_objects }
+    }
+
+    /// Get the closest intersection, returns in an Option<> in case of no intersection.
+    /// Uses the BVH if one has been built, otherwise tests every object
+    pub fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        let Some(SceneBvh { bvh, unbounded_objects }) = self.bvh.get() else {
+            return self.intersect_brute_force(ray);
+        };
+        let mut closest = bvh.closest_hit(ray, |i| self.objects_list[i].intersect(ray));
+        for &i in unbounded_objects {
+            let intersect_temp = self.objects_list[i].intersect(ray);
+            if intersect_temp > EPSILON && closest.is_none_or(|(_, distance)| intersect_temp < distance) {
+                closest = Some((i, intersect_temp));
+            }
+        }
+        closest.map(|(i, distance)| Intersection {
+            distance,
+            index: i,
+            object: self.objects_list[i].as_ref()
+        })
+    }
+
+    /// Get the closest intersection by testing every object in the scene, used to validate the BVH
+    pub fn intersect_brute_force(&self, ray: &Ray) -> Option<Intersection<'_>> {
         let mut closest_intersection:i64 = -1;
         let mut closest_distance = INFINITY;
         for (i,obj) in &mut self.objects_list.iter().enumerate() {
             let intersect_temp = obj.intersect(ray);
-            if intersect_temp > EPSILON {
-                if intersect_temp < closest_distance {
-                    closest_intersection = i as i64;
-                    closest_distance = intersect_temp;
-                }
+            if intersect_temp > EPSILON && intersect_temp < closest_distance {
+                closest_intersection = i as i64;
+                closest_distance = intersect_temp;
             }
         }
         if closest_intersection == -1 {
             return None;
         }
-        return Some(Intersection {
+        Some(Intersection {
             distance: closest_distance,
-            object: &self.objects_list[closest_intersection as usize]
-        });
+            index: closest_intersection as usize,
+            object: self.objects_list[closest_intersection as usize].as_ref()
+        })
     }
 }
 
@@ -420,7 +1324,7 @@ mod tests {
 
     #[test]
     fn vec3d_scalar_mult_test() {
-        let mut test_vec = Vec3D {
+        let test_vec = Vec3D {
             x: 5.0,
             y: 3.0,
             z: 4.0,
@@ -434,7 +1338,7 @@ mod tests {
 
     #[test]
     fn vec3d_scalar_div_test() {
-        let mut test_vec = Vec3D {
+        let test_vec = Vec3D {
             x: 5.0,
             y: 6.0,
             z: 4.0,
@@ -572,4 +1476,235 @@ mod tests {
         assert_eq!(test_vec.dot(&test_vec2), 0.0);
         assert_eq!(test_vec1.dot(&test_vec2), 0.0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn sphere_sample_light_test() {
+        let sphere = Sphere {
+            center: Vec3D {x: 0.0, y: 0.0, z: -4.0},
+            radius: 1.0,
+            material: Material::Emissive { colour: DiffuseColour {r: 12.0, g: 12.0, b: 12.0}, strength: 1.0 },
+        };
+        let origin = Vec3D {x: 0.0, y: 0.0, z: 0.0};
+        // the cone seen from the origin has half angle asin(1/4)
+        let solid_angle = 2.0*PI*(1.0 - (1.0 - 1.0/16.0_f64).sqrt());
+        for random in [(0.0, 0.0), (0.5, 0.25), (0.999, 0.75), (1.0, 1.0)] {
+            let sample = sphere.sample_light(&origin, random).unwrap();
+            assert!((sample.pdf - 1.0/solid_angle).abs() < 1e-9);
+            assert!((sample.direction.length() - 1.0).abs() < 1e-9);
+            if random.0 < 1.0 {
+                assert!(sphere.intersect(&Ray {origin, direction: sample.direction}) > 0.0);
+                assert!((sphere.light_pdf(&origin, &sample.direction) - sample.pdf).abs() < 1e-9);
+            }
+        }
+        assert_eq!(sphere.light_pdf(&origin, &Vec3D {x: 0.0, y: 1.0, z: 0.0}), 0.0);
+
+        // from inside, points of the surface are chosen
+        let sample = sphere.sample_light(&sphere.center, (0.3, 0.6)).unwrap();
+        assert!((sample.pdf - 1.0/(4.0*PI)).abs() < 1e-9);
+        assert!((sphere.light_pdf(&sphere.center, &sample.direction) - sample.pdf).abs() < 1e-9);
+    }
+
+    #[test]
+    fn triangle_intersect_test() {
+        let triangle = Triangle {
+            vertices: [Vec3D {x: -1.0, y: -1.0, z: -2.0}, Vec3D {x: 1.0, y: -1.0, z: -2.0}, Vec3D {x: 0.0, y: 1.0, z: -2.0}],
+            normals: None,
+            uvs: Some([[0.0, 0.0], [1.0, 0.0], [0.5, 1.0]]),
+            material: Material::Diffuse { colour: DiffuseColour {r: 12.0, g: 12.0, b: 12.0} },
+        };
+        let hit = Ray {origin: Vec3D {x: 0.0, y: 0.0, z: 0.0}, direction: Vec3D {x: 0.0, y: 0.0, z: -1.0}};
+        let miss = Ray {origin: Vec3D {x: 2.0, y: 0.0, z: 0.0}, direction: Vec3D {x: 0.0, y: 0.0, z: -1.0}};
+        let behind = Ray {origin: Vec3D {x: 0.0, y: 0.0, z: -3.0}, direction: Vec3D {x: 0.0, y: 0.0, z: -1.0}};
+        assert_eq!(triangle.intersect(&hit), 2.0);
+        assert_eq!(triangle.intersect(&miss), 0.0);
+        assert_eq!(triangle.intersect(&behind), 0.0);
+
+        // counter-clockwise winding faces +z
+        let normal = triangle.surface_normal(&hit, 2.0);
+        assert_eq!(normal.z, 1.0);
+
+        let uv = triangle.uv(&Vec3D {x: 0.0, y: -1.0, z: -2.0}).unwrap();
+        assert!((uv[0] - 0.5).abs() < EPSILON);
+        assert!(uv[1].abs() < EPSILON);
+    }
+
+    #[test]
+    fn triangle_mesh_interpolated_normal_test() {
+        // unit square in the z = -1 plane made of two faces, with normals tilted towards +x on the right edge
+        let positions = vec![
+            Vec3D {x: 0.0, y: 0.0, z: -1.0}, Vec3D {x: 1.0, y: 0.0, z: -1.0},
+            Vec3D {x: 1.0, y: 1.0, z: -1.0}, Vec3D {x: 0.0, y: 1.0, z: -1.0},
+        ];
+        let mut tilted = Vec3D {x: 1.0, y: 0.0, z: 1.0};
+        tilted.norm();
+        let straight = Vec3D {x: 0.0, y: 0.0, z: 1.0};
+        let mesh = TriangleMesh::new(positions, vec![[0, 1, 2], [0, 2, 3]], Material::Diffuse { colour: DiffuseColour {r: 12.0, g: 12.0, b: 12.0} })
+            .unwrap()
+            .with_normals(vec![straight, tilted, tilted, straight]).unwrap()
+            .with_uvs(vec![[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]).unwrap();
+
+        let ray = Ray {origin: Vec3D {x: 0.75, y: 0.25, z: 0.0}, direction: Vec3D {x: 0.0, y: 0.0, z: -1.0}};
+        let distance = mesh.intersect(&ray);
+        assert_eq!(distance, 1.0);
+        let normal = mesh.surface_normal(&ray, distance);
+        assert!(normal.x > 0.0 && normal.z > 0.0);
+        assert!((normal.length() - 1.0).abs() < EPSILON);
+        let uv = mesh.uv(&ray).unwrap();
+        assert!((uv[0] - 0.75).abs() < EPSILON && (uv[1] - 0.25).abs() < EPSILON);
+
+        let outside = Ray {origin: Vec3D {x: 1.5, y: 0.5, z: 0.0}, direction: Vec3D {x: 0.0, y: 0.0, z: -1.0}};
+        assert_eq!(mesh.intersect(&outside), 0.0);
+    }
+
+    #[test]
+    fn triangle_mesh_new_test() {
+        // faces may only refer to positions the mesh has, normals and uvs must cover every position
+        let up = Vec3D {x: 0.0, y: 1.0, z: 0.0};
+        let positions = vec![Vec3D {x: 0.0, y: 0.0, z: 0.0}, Vec3D {x: 1.0, y: 0.0, z: 0.0}, up];
+        let mesh = |indices: Vec<[usize; 3]>| TriangleMesh::new(positions.clone(), indices, Material::Diffuse { colour: DiffuseColour {r: 6.0, g: 6.0, b: 6.0} });
+        assert!(matches!(mesh(Vec::new()), Err(MeshError::NoFaces)));
+        assert!(matches!(mesh(vec![[0, 1, 3]]), Err(MeshError::MissingPosition { index: 3, positions: 3 })));
+        let triangle = || mesh(vec![[0, 1, 2]]).unwrap();
+        assert!(matches!(triangle().with_normals(vec![up]), Err(MeshError::CountMismatch { field: "normals", expected: 3, found: 1 })));
+        assert!(matches!(triangle().with_normals(vec![up, up, Vec3D {x: 0.0, y: 0.0, z: 0.0}]), Err(MeshError::ZeroNormal { index: 2 })));
+        assert!(matches!(triangle().with_uvs(vec![[0.0, 0.0], [f64::NAN, 0.0], [1.0, 1.0]]), Err(MeshError::NonFinite { field: "uvs", index: 1 })));
+        assert_eq!(triangle().with_normals(vec![up, up, up]).unwrap().normals().len(), 3);
+    }
+
+    #[test]
+    fn axis_aligned_box_test() {
+        let table = AxisAlignedBox {
+            min: Vec3D {x: -1.0, y: 0.0, z: -3.0},
+            max: Vec3D {x: 1.0, y: 0.5, z: -2.0},
+            material: Material::Diffuse { colour: DiffuseColour {r: 6.0, g: 6.0, b: 6.0} },
+        };
+        // hits the face towards the viewer, and from inside leaves through the back face
+        let ray = Ray {origin: Vec3D {x: 0.0, y: 0.25, z: 0.0}, direction: Vec3D {x: 0.0, y: 0.0, z: -1.0}};
+        let distance = table.intersect(&ray);
+        assert_eq!(distance, 2.0);
+        assert_eq!(table.surface_normal(&ray, distance).z, 1.0);
+        let inside = Ray {origin: Vec3D {x: 0.0, y: 0.25, z: -2.5}, direction: Vec3D {x: 0.0, y: 0.0, z: -1.0}};
+        assert_eq!(table.intersect(&inside), 0.5);
+        assert_eq!(table.surface_normal(&inside, 0.5).z, -1.0);
+
+        // the top face from above at an angle, and along the top face without touching it
+        let mut direction = Vec3D {x: 1.0, y: -1.0, z: 0.0};
+        direction.norm();
+        let ray = Ray {origin: Vec3D {x: -0.5, y: 1.5, z: -2.5}, direction};
+        let distance = table.intersect(&ray);
+        assert!((distance - 2.0_f64.sqrt()).abs() < EPSILON);
+        assert_eq!(table.surface_normal(&ray, distance).y, 1.0);
+        let grazing = Ray {origin: Vec3D {x: -2.0, y: 0.6, z: -2.5}, direction: Vec3D {x: 1.0, y: 0.0, z: 0.0}};
+        assert_eq!(table.intersect(&grazing), 0.0);
+        let behind = Ray {origin: Vec3D {x: 0.0, y: 0.25, z: 0.0}, direction: Vec3D {x: 0.0, y: 0.0, z: 1.0}};
+        assert_eq!(table.intersect(&behind), 0.0);
+
+        let bounds = table.bounding_box().unwrap();
+        assert_eq!((bounds.min.z, bounds.max.y), (-3.0, 0.5));
+        let flat = AxisAlignedBox {min: Vec3D {x: 0.0, y: 0.0, z: 0.0}, max: Vec3D {x: 1.0, y: 0.0, z: 1.0}, material: Material::Specular};
+        assert!(flat.check().is_err());
+    }
+
+    #[test]
+    fn area_light_test() {
+        let emissive = || Material::Emissive { colour: DiffuseColour {r: 12.0, g: 12.0, b: 12.0}, strength: 1.0 };
+        // a 2 x 1 ceiling panel facing down, and a disk of radius 0.5 facing the viewer
+        let quad = Quad {
+            corner: Vec3D {x: -1.0, y: 2.0, z: -3.5},
+            edge1: Vec3D {x: 2.0, y: 0.0, z: 0.0},
+            edge2: Vec3D {x: 0.0, y: 0.0, z: 1.0},
+            material: emissive(),
+        };
+        let disk = Disk {
+            center: Vec3D {x: 0.0, y: 0.0, z: -4.0},
+            normal: Vec3D {x: 0.0, y: 0.0, z: 1.0},
+            radius: 0.5,
+            material: emissive(),
+        };
+        assert_eq!(quad.area(), 2.0);
+        assert_eq!(quad.unit_normal().y, -1.0);
+        assert!((disk.area() - PI/4.0).abs() < EPSILON);
+
+        let up = Ray {origin: Vec3D {x: 0.9, y: 0.0, z: -2.6}, direction: Vec3D {x: 0.0, y: 1.0, z: 0.0}};
+        assert_eq!(quad.intersect(&up), 2.0);
+        let past_edge = Ray {origin: Vec3D {x: 1.1, y: 0.0, z: -3.0}, direction: Vec3D {x: 0.0, y: 1.0, z: 0.0}};
+        assert_eq!(quad.intersect(&past_edge), 0.0);
+        let ahead = Ray {origin: Vec3D {x: 0.3, y: 0.3, z: 0.0}, direction: Vec3D {x: 0.0, y: 0.0, z: -1.0}};
+        assert_eq!(disk.intersect(&ahead), 4.0);
+        let past_rim = Ray {origin: Vec3D {x: 0.4, y: 0.4, z: 0.0}, direction: Vec3D {x: 0.0, y: 0.0, z: -1.0}};
+        assert_eq!(disk.intersect(&past_rim), 0.0);
+        let bounds = disk.bounding_box().unwrap();
+        assert_eq!((bounds.min.x, bounds.max.y, bounds.min.z, bounds.max.z), (-0.5, 0.5, -4.0, -4.0));
+
+        // sampled points lie on the surface, and light_pdf agrees with the density of the sampled direction
+        let reference = Vec3D {x: 0.2, y: -0.5, z: -1.0};
+        let light: [&dyn SceneObject; 2] = [&quad, &disk];
+        for object in light {
+            for random in [(0.1, 0.2), (0.5, 0.5), (0.9, 0.7), (0.3, 0.95)] {
+                let sample = object.sample_light(&reference, random).unwrap();
+                let pdf = object.light_pdf(&reference, &sample.direction);
+                assert!((pdf - sample.pdf).abs() < 1e-9*sample.pdf, "{pdf} != {}", sample.pdf);
+            }
+        }
+        for random in [(0.0, 0.0), (1.0, 1.0), (0.25, 0.75)] {
+            let point = disk.sample_point(random);
+            assert!(point.z == -4.0 && point.subtract(&disk.center).length() <= 0.5 + EPSILON);
+        }
+        let point = quad.sample_point((1.0, 1.0));
+        assert_eq!((point.x, point.y, point.z), (1.0, 2.0, -2.5));
+    }
+
+    #[test]
+    fn render_scene_check_test() {
+        let diffuse = || Material::Diffuse { colour: DiffuseColour {r: 6.0, g: 6.0, b: 6.0} };
+        let sphere = |radius: f64, material: Material| -> Box<dyn SceneObject> {
+            Box::new(Sphere { center: Vec3D {x: 0.0, y: 0.0, z: 0.0}, radius, material })
+        };
+        let plane = |normal: Vec3D| -> Box<dyn SceneObject> {
+            Box::new(Plane { normal, distance_to_origin: 1.0, material: diffuse() })
+        };
+        let up = Vec3D {x: 0.0, y: 1.0, z: 0.0};
+        assert!(RenderScene::new(vec![sphere(1.0, diffuse()), plane(up)]).is_ok());
+
+        let invalid = |objects_list: Vec<Box<dyn SceneObject>>| match RenderScene::new(objects_list) {
+            Err(Error::InvalidObject { index, .. }) => index,
+            _ => panic!("expected an invalid object"),
+        };
+        assert_eq!(invalid(vec![plane(up), sphere(-1.0, diffuse())]), 1);
+        assert_eq!(invalid(vec![plane(Vec3D {x: 0.0, y: 2.0, z: 0.0})]), 0);
+        assert_eq!(invalid(vec![sphere(1.0, diffuse()), sphere(1.0, Material::Refractive { refractive_index: 0.0 })]), 1);
+        // NaN fails every comparison, so each parameter check must refuse it explicitly
+        assert_eq!(invalid(vec![sphere(f64::NAN, diffuse())]), 0);
+        assert_eq!(invalid(vec![sphere(1.0, Material::Refractive { refractive_index: f64::NAN })]), 0);
+        let disk = |radius: f64| -> Box<dyn SceneObject> {
+            Box::new(Disk { center: Vec3D {x: 0.0, y: 0.0, z: 0.0}, normal: up, radius, material: diffuse() })
+        };
+        assert!(RenderScene::new(vec![disk(1.0)]).is_ok());
+        assert_eq!(invalid(vec![disk(f64::NAN)]), 0);
+        let quad = |edge2: Vec3D| -> Box<dyn SceneObject> {
+            Box::new(Quad { corner: Vec3D {x: 0.0, y: 0.0, z: 0.0}, edge1: Vec3D {x: 1.0, y: 0.0, z: 0.0}, edge2, material: diffuse() })
+        };
+        assert!(RenderScene::new(vec![quad(up)]).is_ok());
+        assert_eq!(invalid(vec![quad(Vec3D {x: 0.0, y: f64::NAN, z: 0.0})]), 0);
+
+        // triangles must be finite and have an area, meshes may only refer to positions they have and need non-zero normals
+        let triangle = |corner: Vec3D| -> Box<dyn SceneObject> {
+            Box::new(Triangle { vertices: [Vec3D {x: 0.0, y: 0.0, z: 0.0}, Vec3D {x: 1.0, y: 0.0, z: 0.0}, corner], normals: None, uvs: None, material: diffuse() })
+        };
+        let positions = vec![Vec3D {x: 0.0, y: 0.0, z: 0.0}, Vec3D {x: 1.0, y: 0.0, z: 0.0}, up];
+        let mesh = || TriangleMesh::new(positions.clone(), vec![[0, 1, 2]], diffuse()).unwrap();
+        assert!(RenderScene::new(vec![triangle(up), Box::new(mesh())]).is_ok());
+        assert_eq!(invalid(vec![triangle(up), triangle(Vec3D {x: 2.0, y: 0.0, z: 0.0})]), 1);
+        assert_eq!(invalid(vec![triangle(Vec3D {x: 0.0, y: f64::NAN, z: 0.0})]), 0);
+        assert!(TriangleMesh::new(positions.clone(), vec![[0, 1, 3]], diffuse()).is_err());
+        let mut changed = mesh();
+        changed.indices.push([2, 1, 3]);
+        assert_eq!(invalid(vec![Box::new(mesh()), Box::new(changed)]), 1);
+        let mut changed = mesh();
+        changed.positions[2].z = f64::INFINITY;
+        assert_eq!(invalid(vec![Box::new(changed)]), 0);
+        let mut changed = mesh().with_normals(vec![up, up, up]).unwrap();
+        changed.normals[1] = Vec3D {x: 0.0, y: 0.0, z: 0.0};
+        assert_eq!(invalid(vec![Box::new(changed)]), 0);
+    }
+}
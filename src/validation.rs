@@ -0,0 +1,179 @@
+use std::f64::consts::PI;
+use std::fmt;
+
+use crate::type_structs::RenderScene;
+
+// Scene validation
+//
+// RenderScene::validate looks for mistakes that RenderScene::new lets through or that only show up once objects have
+// been changed after construction:
+// - the parameters SceneObject::check refuses, such as NaN or infinite coordinates, negative radii, planes with normals
+//   not of unit length, triangles without area, mesh indices without a position and refractive indices of zero or less
+// - degenerate geometry that still renders, such as mesh faces without area
+// - material settings that are invalid (negative or non-finite colours) or have no effect (lights that emit nothing,
+//   glass with a refractive index of 1), and surfaces that reflect more light than they receive
+// - emissive objects that cannot be sampled as lights, and scenes without anything that emits light
+// - a camera that does not define a view
+// Errors make the render fail or produce meaningless images, warnings point at settings that are probably not meant.
+
+/// # How serious a problem found by RenderScene::validate is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The scene renders, but probably not as intended
+    Warning,
+    /// The scene cannot be rendered meaningfully
+    Error,
+}
+
+/// # A problem found by RenderScene::validate
+/// `object` is the index into the scene's objects_list, None for problems with the scene as a whole
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidationIssue {
+    pub severity: Severity,
+    pub object: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.object {
+            Some(index) => write!(f, "{}: object {index}: {}", self.severity, self.message),
+            None => write!(f, "{}: {}", self.severity, self.message),
+        }
+    }
+}
+
+impl RenderScene {
+    /// Check every object, its material and the camera, and whether anything emits light.
+    /// Returns every problem found, errors and warnings alike; an empty list means the scene looks fine
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        let mut issues = Vec::new();
+        let mut emissive = false;
+        for (index, object) in self.objects_list.iter().enumerate() {
+            let mut problems = object.validate_geometry();
+            if let Err(message) = object.check() {
+                problems.push((Severity::Error, message));
+            }
+            let material = object.material();
+            problems.extend(material.validate());
+
+            let emitted = material.emitted();
+            if emitted.r + emitted.g + emitted.b > 0.0 {
+                emissive = true;
+                if !object.supports_light_sampling() {
+                    problems.push((Severity::Warning, "emits light but cannot be sampled as a light, so its light is only \
+                                                       found by paths that happen to hit it".to_string()));
+                }
+            }
+            issues.extend(problems.into_iter().map(|(severity, message)| ValidationIssue { severity, object: Some(index), message }));
+        }
+
+        if !emissive {
+            issues.push(ValidationIssue {
+                severity: Severity::Warning,
+                object: None,
+                message: "no object emits light, the image will be black".to_string(),
+            });
+        }
+
+        let camera = &self.camera;
+        let camera_finite = [camera.eye, camera.target, camera.up].iter().all(|vector| vector.is_finite())
+            && camera.vertical_fov.is_finite() && camera.aperture_radius.is_finite();
+        let camera_problem = if !camera_finite {
+            Some("camera has NaN or infinite values")
+        }else if camera.vertical_fov <= 0.0 || camera.vertical_fov >= PI {
+            Some("camera vertical_fov must lie between 0 and 180 degrees")
+        }else if camera.frame(1, 1).is_none() {
+            Some("camera eye and target must differ and up must not be parallel to the viewing direction")
+        }else if camera.aperture_radius < 0.0 {
+            Some("camera aperture_radius must not be negative")
+        }else if camera.focus_distance.is_some_and(|distance| !(distance.is_finite() && distance > 0.0)) {
+            Some("camera focus_distance must be greater than zero")
+        }else {
+            None
+        };
+        if let Some(message) = camera_problem {
+            issues.push(ValidationIssue { severity: Severity::Error, object: None, message: message.to_string() });
+        }
+        issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+    use crate::type_structs::{DiffuseColour, Plane, SceneObject, Sphere, Triangle, Vec3D};
+
+    fn grey() -> Material {
+        Material::Diffuse { colour: DiffuseColour {r: 6.0, g: 6.0, b: 6.0} }
+    }
+
+    fn light() -> Box<dyn SceneObject> {
+        Box::new(Sphere {
+            center: Vec3D {x: 0.0, y: 4.0, z: -3.0},
+            radius: 1.0,
+            material: Material::Emissive { colour: DiffuseColour {r: 12.0, g: 12.0, b: 12.0}, strength: 10.0 },
+        })
+    }
+
+    fn issues(scene: &RenderScene) -> Vec<(Severity, Option<usize>)> {
+        scene.validate().iter().map(|issue| (issue.severity, issue.object)).collect()
+    }
+
+    #[test]
+    fn validate_test() {
+        let floor = || Box::new(Plane { normal: Vec3D {x: 0.0, y: 1.0, z: 0.0}, distance_to_origin: 0.0, material: grey() });
+        let mut scene = RenderScene::new(vec![floor(), light()]).unwrap();
+        assert!(scene.validate().is_empty(), "{:?}", scene.validate());
+
+        // problems that only appear once the scene is changed after construction
+        scene.objects_list.push(Box::new(Sphere { center: Vec3D {x: f64::NAN, y: 0.0, z: 0.0}, radius: 1.0, material: grey() }));
+        scene.objects_list.push(Box::new(Triangle {
+            vertices: [Vec3D {x: 0.0, y: 0.0, z: 0.0}, Vec3D {x: 1.0, y: 1.0, z: 1.0}, Vec3D {x: 2.0, y: 2.0, z: 2.0}],
+            normals: None,
+            uvs: None,
+            material: grey(),
+        }));
+        scene.objects_list.push(Box::new(Sphere { center: Vec3D {x: 0.0, y: 0.0, z: 0.0}, radius: -1.0, material: grey() }));
+        scene.objects_list.push(Box::new(Sphere {
+            center: Vec3D {x: 0.0, y: 0.0, z: 0.0},
+            radius: 1.0,
+            material: Material::Refractive { refractive_index: 1.0 },
+        }));
+        scene.objects_list.push(Box::new(Sphere {
+            center: Vec3D {x: 0.0, y: 0.0, z: 0.0},
+            radius: 1.0,
+            material: Material::Diffuse { colour: DiffuseColour {r: 20.0, g: 6.0, b: 6.0} },
+        }));
+        assert_eq!(issues(&scene), vec![
+            (Severity::Error, Some(2)),
+            (Severity::Error, Some(3)),
+            (Severity::Error, Some(4)),
+            (Severity::Warning, Some(5)),
+            (Severity::Warning, Some(6)),
+        ]);
+
+        // an emissive plane lights the scene but is never sampled as a light
+        let mut scene = RenderScene::new(vec![Box::new(Plane {
+            normal: Vec3D {x: 0.0, y: -1.0, z: 0.0},
+            distance_to_origin: 5.0,
+            material: Material::Emissive { colour: DiffuseColour {r: 12.0, g: 12.0, b: 12.0}, strength: 1.0 },
+        })]).unwrap();
+        assert_eq!(issues(&scene), vec![(Severity::Warning, Some(0))]);
+        // no light at all, and a camera looking nowhere
+        scene.objects_list[0] = floor();
+        scene.camera.target = scene.camera.eye;
+        assert_eq!(issues(&scene), vec![(Severity::Warning, None), (Severity::Error, None)]);
+        assert_eq!(scene.validate()[0].to_string(), "warning: no object emits light, the image will be black");
+    }
+}